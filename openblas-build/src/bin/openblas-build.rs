@@ -0,0 +1,428 @@
+//! Manage OpenBLAS builds cached by `cache` feature of openblas-src
+//!
+//! ```text
+//! openblas-build [--cache-dir <DIR>] <COMMAND>
+//! ```
+
+use openblas_build::*;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+
+const USAGE: &str = "\
+Manage OpenBLAS builds cached by `cache` feature of openblas-src
+
+Usage: openblas-build [--cache-dir <DIR>] <COMMAND>
+
+Commands:
+  list                           List cached builds
+  show <DIGEST>                  Show configuration, version, size and last use of a build
+  verify                         Check that cached builds are complete
+  prune --older-than <DURATION>  Remove builds not used for DURATION, e.g. 30d, 12h, 90m
+  clean                          Remove all cached builds and source tarballs
+
+Options:
+  --cache-dir <DIR>  Cache directory [default: openblas_build in user's data directory]
+  -h, --help         Print help
+";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    List,
+    Show { digest: String },
+    Verify,
+    Prune { older_than: Duration },
+    Clean,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    cache_dir: PathBuf,
+    command: Command,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut cache_dir = None;
+    let mut older_than = None;
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--cache-dir" => {
+                let dir = iter.next().ok_or("--cache-dir requires a directory")?;
+                cache_dir = Some(PathBuf::from(dir));
+            }
+            "--older-than" => {
+                let duration = iter.next().ok_or("--older-than requires a duration")?;
+                older_than = Some(parse_duration(duration)?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    let command = match positional.as_slice() {
+        ["list"] => Command::List,
+        ["show", digest] => Command::Show {
+            digest: digest.to_string(),
+        },
+        ["show"] => return Err("show requires a digest".into()),
+        ["verify"] => Command::Verify,
+        ["prune"] => Command::Prune {
+            older_than: older_than.ok_or("prune requires --older-than")?,
+        },
+        ["clean"] => Command::Clean,
+        [] => return Err("No command is specified".into()),
+        _ => return Err(format!("Unknown command: {}", positional.join(" "))),
+    };
+    if older_than.is_some() && !matches!(command, Command::Prune { .. }) {
+        return Err("--older-than is only for prune".into());
+    }
+    let cache_dir = match cache_dir {
+        Some(dir) => dir,
+        None => cache_root().ok_or("Cannot get user's data directory")?,
+    };
+    Ok(Args { cache_dir, command })
+}
+
+/// Parse duration like `30d`, `12h`, `90m` or `3600s`. Seconds if no unit is given.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", s))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration: {}", s)),
+    };
+    number
+        .checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration: {}", s))
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn format_age(time: Option<SystemTime>) -> String {
+    let age = match time.and_then(|time| time.elapsed().ok()) {
+        Some(age) => age.as_secs(),
+        None => return "unknown".into(),
+    };
+    if age >= 24 * 60 * 60 {
+        format!("{} days ago", age / (24 * 60 * 60))
+    } else if age >= 60 * 60 {
+        format!("{} hours ago", age / (60 * 60))
+    } else {
+        format!("{} minutes ago", age / 60)
+    }
+}
+
+fn version(entry: &CacheEntry) -> String {
+    entry.version().unwrap_or_else(|| "unknown".into())
+}
+
+/// Remove the build unless another process is using it
+fn remove(entry: &CacheEntry) -> bool {
+    match BuildLock::try_acquire(entry.path.join(".lock")) {
+        Ok(Some(_lock)) => match entry.remove() {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Cannot remove {}: {}", entry.path.display(), e);
+                false
+            }
+        },
+        Ok(None) => {
+            eprintln!("Skip {}: used by another build", entry.path.display());
+            false
+        }
+        Err(e) => {
+            eprintln!("Skip {}: {}", entry.path.display(), e);
+            false
+        }
+    }
+}
+
+/// Tarball which a file in `sources` belongs to, e.g. `0.3.31-{sha256}` for
+/// `0.3.31-{sha256}.tar.gz` and its lock file `.0.3.31-{sha256}.lock`
+fn tarball_name(file_name: &str) -> &str {
+    let name = file_name.strip_prefix('.').unwrap_or(file_name);
+    [".tar.gz.part", ".tar.gz", ".lock"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(name)
+}
+
+/// Remove source tarballs in `sources` unless another process is downloading them
+fn clean_sources(sources: &Path) -> Result<bool, String> {
+    let read_dir = |dir: &Path| -> Result<Vec<PathBuf>, String> {
+        fs::read_dir(dir)
+            .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+            .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))
+    };
+    let mut names: Vec<String> = read_dir(sources)?
+        .iter()
+        .filter_map(|path| Some(tarball_name(path.file_name()?.to_str()?).to_string()))
+        .collect();
+    names.sort();
+    names.dedup();
+    let mut ok = true;
+    for name in names {
+        let lock_path = sources.join(format!(".{}.lock", name));
+        let _lock = match BuildLock::try_acquire(&lock_path) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                eprintln!("Skip {}: downloaded by another build", name);
+                ok = false;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Skip {}: {}", name, e);
+                ok = false;
+                continue;
+            }
+        };
+        for path in read_dir(sources)? {
+            let belongs = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| tarball_name(file_name) == name);
+            if belongs {
+                if let Err(e) = fs::remove_file(&path) {
+                    eprintln!("Cannot remove {}: {}", path.display(), e);
+                    ok = false;
+                }
+            }
+        }
+    }
+    if ok {
+        let _ = fs::remove_dir(sources);
+    }
+    Ok(ok)
+}
+
+fn run(args: Args) -> Result<bool, String> {
+    let entries = CacheEntry::list(&args.cache_dir)
+        .map_err(|e| format!("Cannot read {}: {}", args.cache_dir.display(), e))?;
+    match args.command {
+        Command::List => {
+            for entry in &entries {
+                println!(
+                    "{}  {:<8}  {:>10}  {}",
+                    entry.digest(),
+                    version(entry),
+                    format_size(entry.size()),
+                    format_age(entry.last_used()),
+                );
+            }
+        }
+        Command::Show { digest } => {
+            let found: Vec<_> = entries
+                .iter()
+                .filter(|entry| entry.digest().starts_with(&digest))
+                .collect();
+            let entry = match found.as_slice() {
+                [entry] => entry,
+                [] => return Err(format!("No build matches {}", digest)),
+                _ => return Err(format!("Ambiguous digest: {}", digest)),
+            };
+            println!("Path:      {}", entry.path.display());
+            println!("Version:   {}", version(entry));
+            println!("Size:      {}", format_size(entry.size()));
+            println!("Last used: {}", format_age(entry.last_used()));
+            match entry.verify() {
+                Ok(make_conf) => {
+                    println!("Status:    complete");
+                    println!("OS:        {}", make_conf.os_name);
+                    println!("Compiler:  {}", make_conf.c_compiler);
+                    println!("Fortran:   {}", !make_conf.no_fortran);
+                }
+                Err(e) => println!("Status:    invalid ({})", e),
+            }
+            println!("Key:");
+            print!("{}", entry.key_json().unwrap_or_default());
+        }
+        Command::Verify => {
+            let mut ok = true;
+            for entry in &entries {
+                match entry.verify() {
+                    Ok(_) => println!("{}  ok", entry.digest()),
+                    Err(e) => {
+                        println!("{}  invalid: {}", entry.digest(), e);
+                        ok = false;
+                    }
+                }
+            }
+            return Ok(ok);
+        }
+        Command::Prune { older_than } => {
+            let mut freed = 0;
+            for entry in &entries {
+                let unused = entry
+                    .last_used()
+                    .and_then(|time| time.elapsed().ok())
+                    .map_or(true, |age| age > older_than);
+                if unused {
+                    let size = entry.size();
+                    if remove(entry) {
+                        println!("Removed {}", entry.digest());
+                        freed += size;
+                    }
+                }
+            }
+            println!("Freed {}", format_size(freed));
+        }
+        Command::Clean => {
+            let mut ok = true;
+            for entry in &entries {
+                ok &= remove(entry);
+            }
+            let sources = args.cache_dir.join("sources");
+            if sources.exists() {
+                ok &= clean_sources(&sources)?;
+            }
+            return Ok(ok);
+        }
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        let args: Vec<String> = ["--cache-dir", "/tmp/cache"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("3600"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("30d"),
+            Ok(Duration::from_secs(30 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("2w"),
+            Ok(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        for invalid in ["", "d", "30y", "1.5h", "-1d", "99999999999999w"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn command_line() {
+        assert_eq!(
+            args(&["list"]),
+            Ok(Args {
+                cache_dir: PathBuf::from("/tmp/cache"),
+                command: Command::List,
+            })
+        );
+        assert_eq!(
+            args(&["show", "abc"]).unwrap().command,
+            Command::Show {
+                digest: "abc".into()
+            }
+        );
+        assert_eq!(
+            args(&["prune", "--older-than", "30d"]).unwrap().command,
+            Command::Prune {
+                older_than: Duration::from_secs(30 * 24 * 60 * 60)
+            }
+        );
+        assert_eq!(args(&["clean"]).unwrap().command, Command::Clean);
+        assert_eq!(args(&["verify"]).unwrap().command, Command::Verify);
+
+        assert!(args(&[]).is_err());
+        assert!(args(&["show"]).is_err());
+        assert!(args(&["prune"]).is_err());
+        assert!(args(&["list", "--older-than", "1d"]).is_err());
+        assert!(args(&["list", "--unknown"]).is_err());
+        assert!(args(&["list", "extra"]).is_err());
+        assert!(parse_args(&["list".to_string(), "--cache-dir".to_string()]).is_err());
+    }
+
+    #[test]
+    fn clean_locked_sources() {
+        let sources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build/clean_sources");
+        let _ = fs::remove_dir_all(&sources);
+        fs::create_dir_all(&sources).unwrap();
+        let files = [
+            "0.3.30-aaaa.tar.gz",
+            ".0.3.30-aaaa.lock",
+            ".0.3.31-bbbb.tar.gz.part",
+            ".0.3.31-bbbb.lock",
+        ];
+        for file in files {
+            fs::write(sources.join(file), "").unwrap();
+        }
+        assert_eq!(tarball_name(files[0]), "0.3.30-aaaa");
+        assert_eq!(tarball_name(files[2]), "0.3.31-bbbb");
+
+        // The tarball being downloaded by another build is kept
+        let lock = BuildLock::try_acquire(sources.join(files[3]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(clean_sources(&sources), Ok(false));
+        assert!(!sources.join(files[0]).exists());
+        assert!(!sources.join(files[1]).exists());
+        assert!(sources.join(files[2]).exists());
+
+        drop(lock);
+        assert_eq!(clean_sources(&sources), Ok(true));
+        assert!(!sources.exists());
+    }
+}
@@ -1,7 +1,7 @@
 //! Execute make of OpenBLAS, and its options
 
-use crate::{check::*, error::*};
-use std::{env, fs, path::*, process::Command, str::FromStr};
+use crate::{cache::CacheKey, check::*, download::OPENBLAS_VERSION, error::*, package::*};
+use std::{env, fs, io, path::*, process::Command, str::FromStr};
 
 /// Interface for 32-bit interger (LP64) and 64-bit integer (ILP64)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -319,25 +319,88 @@ impl FromStr for Target {
     }
 }
 
-impl Target {
-    fn get_generic_target() -> Option<Self> {
-        let target = env::var("TARGET").unwrap();
-        let target_arch = target.split('-').nth(0).unwrap();
-        match target_arch {
-            "aarch64" => Some(Target::ARMV8),
-            "arm" => Some(Target::ARMV6),
-            "armv5te" => Some(Target::ARMV5),
-            "armv6" => Some(Target::ARMV6),
-            "armv7" => Some(Target::ARMV7),
-            "loongarch64" => Some(Target::LOONGSONGENERIC),
-            "mips64" => Some(Target::MIPS64_GENERIC),
-            "mips64el" => Some(Target::MIPS64_GENERIC),
-            "riscv64gc" => Some(Target::RISCV64_GENERIC),
-            "csky" => Some(Target::CK860FV),
-            "sparc" => Some(Target::SPARCV7),
-            //TODO: add more generic targets
-            _ => None,
+/// Precisions of BLAS and LAPACK routines to be built
+///
+/// All precisions are built by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Precisions {
+    /// Real single precision, e.g. `sgemm` (`BUILD_SINGLE`)
+    pub single: bool,
+    /// Real double precision, e.g. `dgemm` (`BUILD_DOUBLE`)
+    pub double: bool,
+    /// Complex single precision, e.g. `cgemm` (`BUILD_COMPLEX`)
+    pub complex: bool,
+    /// Complex double precision, e.g. `zgemm` (`BUILD_COMPLEX16`)
+    pub complex16: bool,
+}
+
+impl Default for Precisions {
+    fn default() -> Self {
+        Precisions {
+            single: true,
+            double: true,
+            complex: true,
+            complex16: true,
+        }
+    }
+}
+
+impl Precisions {
+    fn make_args(&self) -> Result<Vec<String>, Error> {
+        let precisions = [
+            ("BUILD_SINGLE", self.single),
+            ("BUILD_DOUBLE", self.double),
+            ("BUILD_COMPLEX", self.complex),
+            ("BUILD_COMPLEX16", self.complex16),
+        ];
+        if precisions.iter().all(|(_, enabled)| *enabled) {
+            // OpenBLAS builds all precisions if none of `BUILD_*` is set
+            return Ok(Vec::new());
         }
+        if precisions.iter().all(|(_, enabled)| !*enabled) {
+            return Err(Error::NoPrecision);
+        }
+        Ok(precisions
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| format!("{}=1", name))
+            .collect())
+    }
+}
+
+/// Threading model of OpenBLAS
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Threading {
+    /// OpenBLAS enables threading when the build host has multiple cores
+    #[default]
+    Auto,
+    /// POSIX threads (`USE_THREAD=1`)
+    Pthreads,
+    /// OpenMP (`USE_OPENMP=1`)
+    OpenMP,
+    /// Single-threaded (`USE_THREAD=0`)
+    Serial,
+}
+
+impl Threading {
+    /// Name of the threading model, e.g. `pthreads`, or `None` for [Threading::Auto]
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Threading::Auto => None,
+            Threading::Pthreads => Some("pthreads"),
+            Threading::OpenMP => Some("openmp"),
+            Threading::Serial => Some("serial"),
+        }
+    }
+
+    fn make_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Threading::Auto => &[],
+            Threading::Pthreads => &["USE_THREAD=1"],
+            Threading::OpenMP => &["USE_THREAD=1", "USE_OPENMP=1"],
+            Threading::Serial => &["USE_THREAD=0"],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
     }
 }
 
@@ -352,43 +415,139 @@ pub struct Compilers {
 /// make option generator
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Configure {
+    /// Version of OpenBLAS, see [crate::OPENBLAS_VERSIONS]
+    pub version: String,
     pub no_static: bool,
     pub no_shared: bool,
     pub no_cblas: bool,
     pub no_lapack: bool,
     pub no_lapacke: bool,
-    pub use_thread: bool,
-    pub use_openmp: bool,
+    pub threading: Threading,
     pub dynamic_arch: bool,
+    /// Targets to be included in `DYNAMIC_ARCH` build (`DYNAMIC_LIST`).
+    /// All targets supported by OpenBLAS are included if empty.
+    pub dynamic_list: Vec<Target>,
     pub interface: Interface,
+    pub precisions: Precisions,
+    /// Build bfloat16 routines, e.g. `sbgemm` (`BUILD_BFLOAT16`)
+    pub build_bfloat16: bool,
+    /// Build half-precision routines, e.g. `shgemm` (`BUILD_HFLOAT16`)
+    pub build_hfloat16: bool,
     pub target: Option<Target>,
     pub compilers: Compilers,
+
+    // Options below are passed to `make` only when they are set,
+    // and OpenBLAS uses its own default otherwise.
+    /// Maximum number of threads (`NUM_THREADS`)
+    pub num_threads: Option<u32>,
+    /// Maximum number of concurrent BLAS calls from different threads (`NUM_PARALLEL`)
+    pub num_parallel: Option<u32>,
+    /// Make single-threaded library thread-safe (`USE_LOCKING`)
+    pub use_locking: Option<bool>,
+    /// Disable CPU and memory affinity (`NO_AFFINITY`)
+    pub no_affinity: Option<bool>,
+    /// Size of memory buffer as the exponent of 2, e.g. 25 for 32 MB (`BUFFERSIZE`)
+    pub buffer_size: Option<u32>,
+    /// Size threshold to use multiple threads in GEMM (`GEMM_MULTITHREAD_THRESHOLD`)
+    pub gemm_multithread_threshold: Option<u32>,
+    /// Maximum size in bytes of buffers allocated on stack (`MAX_STACK_ALLOC`)
+    pub max_stack_alloc: Option<u32>,
+    /// Share floating-point control register of the main thread with workers (`CONSISTENT_FPCSR`)
+    pub consistent_fpcsr: Option<bool>,
+    /// Use thread-local storage for memory allocation (`USE_TLS`)
+    pub use_tls: Option<bool>,
+    /// Disable AVX512 kernels (`NO_AVX512`)
+    pub no_avx512: Option<bool>,
+    /// Disable AVX2 kernels (`NO_AVX2`)
+    pub no_avx2: Option<bool>,
+    /// Build recursive LAPACK, ReLAPACK (`BUILD_RELAPACK`)
+    pub build_relapack: Option<bool>,
+    /// Use kernels optimized for small matrices (`SMALL_MATRIX_OPT`)
+    pub small_matrix_opt: Option<bool>,
 }
 
 impl Default for Configure {
     fn default() -> Self {
         Configure {
+            version: OPENBLAS_VERSION.to_string(),
             no_static: false,
             no_shared: false,
             no_cblas: false,
             no_lapack: false,
             no_lapacke: false,
-            use_thread: false,
-            use_openmp: false,
+            threading: Threading::default(),
             dynamic_arch: false,
+            dynamic_list: Vec::new(),
             interface: Interface::LP64,
+            precisions: Precisions::default(),
+            build_bfloat16: false,
+            build_hfloat16: false,
             target: None,
             compilers: Compilers::default(),
+            num_threads: None,
+            num_parallel: None,
+            use_locking: None,
+            no_affinity: None,
+            buffer_size: None,
+            gemm_multithread_threshold: None,
+            max_stack_alloc: None,
+            consistent_fpcsr: None,
+            use_tls: None,
+            no_avx512: None,
+            no_avx2: None,
+            build_relapack: None,
+            small_matrix_opt: None,
         }
     }
 }
 
+/// File name of the stamp written into `build_dir` of [Configure::build] after the build completes,
+/// which contains [Configure::fingerprint]
+pub const BUILD_STAMP: &str = "openblas-build.stamp";
+
+/// Variables of OpenBLAS `make` which [Configure] specifies.
+///
+/// They are removed from the environment of `make`
+/// so that only [Configure] determines the build.
+const CONFIGURE_VARIABLES: &[&str] = &[
+    "NO_STATIC",
+    "NO_SHARED",
+    "NO_CBLAS",
+    "NO_LAPACK",
+    "NO_LAPACKE",
+    "USE_THREAD",
+    "USE_OPENMP",
+    "DYNAMIC_ARCH",
+    "DYNAMIC_LIST",
+    "INTERFACE64",
+    "BUILD_SINGLE",
+    "BUILD_DOUBLE",
+    "BUILD_COMPLEX",
+    "BUILD_COMPLEX16",
+    "BUILD_BFLOAT16",
+    "BUILD_HFLOAT16",
+    "NUM_THREADS",
+    "NUM_PARALLEL",
+    "USE_LOCKING",
+    "NO_AFFINITY",
+    "BUFFERSIZE",
+    "GEMM_MULTITHREAD_THRESHOLD",
+    "MAX_STACK_ALLOC",
+    "CONSISTENT_FPCSR",
+    "USE_TLS",
+    "NO_AVX512",
+    "NO_AVX2",
+    "BUILD_RELAPACK",
+    "SMALL_MATRIX_OPT",
+];
+
 impl Configure {
-    fn make_args(&self) -> Result<Vec<String>, Error> {
+    pub(crate) fn make_args(&self) -> Result<Vec<String>, Error> {
         // check if it is cross-compilation
         let build_target = env::var("TARGET").unwrap_or_default();
         let build_host = env::var("HOST").unwrap_or_default();
         let is_cross_compile = build_target != build_host;
+        let is_musl = build_target.contains("-musl");
 
         let mut args = Vec::new();
         if self.no_static {
@@ -406,31 +565,76 @@ impl Configure {
         if self.no_lapacke {
             args.push("NO_LAPACKE=1".into());
         }
-        if self.use_thread {
-            args.push("USE_THREAD=1".into());
-        }
-        if self.use_openmp {
-            args.push("USE_OPENMP=1".into());
+        args.extend(self.threading.make_args());
+        if self.dynamic_arch {
+            args.push("DYNAMIC_ARCH=1".into());
+            if !self.dynamic_list.is_empty() {
+                let list: Vec<_> = self
+                    .dynamic_list
+                    .iter()
+                    .map(|target| format!("{:?}", target))
+                    .collect();
+                args.push(format!("DYNAMIC_LIST={}", list.join(" ")));
+            }
         }
         if matches!(self.interface, Interface::ILP64) {
             args.push("INTERFACE64=1".into());
         }
+        args.extend(self.precisions.make_args()?);
+        if self.build_bfloat16 {
+            args.push("BUILD_BFLOAT16=1".into());
+        }
+        if self.build_hfloat16 {
+            args.push("BUILD_HFLOAT16=1".into());
+        }
+        let numbers = [
+            ("NUM_THREADS", self.num_threads),
+            ("NUM_PARALLEL", self.num_parallel),
+            ("BUFFERSIZE", self.buffer_size),
+            (
+                "GEMM_MULTITHREAD_THRESHOLD",
+                self.gemm_multithread_threshold,
+            ),
+            ("MAX_STACK_ALLOC", self.max_stack_alloc),
+        ];
+        for (name, value) in numbers {
+            if let Some(value) = value {
+                args.push(format!("{}={}", name, value));
+            }
+        }
+        let switches = [
+            ("USE_LOCKING", self.use_locking),
+            // CPU affinity and thread-local storage of OpenBLAS depend on glibc
+            ("NO_AFFINITY", self.no_affinity.or(is_musl.then_some(true))),
+            ("CONSISTENT_FPCSR", self.consistent_fpcsr),
+            ("USE_TLS", self.use_tls.or(is_musl.then_some(false))),
+            ("NO_AVX512", self.no_avx512),
+            ("NO_AVX2", self.no_avx2),
+            ("BUILD_RELAPACK", self.build_relapack),
+            ("SMALL_MATRIX_OPT", self.small_matrix_opt),
+            // 32-bit ARM Android uses softfp ABI even with VFP and NEON
+            (
+                "ARM_SOFTFP_ABI",
+                build_target.ends_with("-androideabi").then_some(true),
+            ),
+        ];
+        for (name, value) in switches {
+            if let Some(value) = value {
+                args.push(format!("{}={}", name, value as u8));
+            }
+        }
         if let Some(target) = self.target.as_ref() {
             args.push(format!("TARGET={:?}", target));
-        } else if is_cross_compile {
-            if let Some(target) = Target::get_generic_target() {
-                args.push(format!("TARGET={:?}", target));
-            } else {
-                return Err(Error::MissingCrossCompileInfo {
-                    info: "TARGET".to_string(),
-                });
-            }
+        } else if let Some(target) = Target::for_cross_compile(&build_target, &build_host)? {
+            args.push(format!("TARGET={:?}", target));
         }
 
         let mut cc_compiler = self.compilers.cc.clone();
         if let Some(cc) = self.compilers.cc.as_ref() {
             args.push(format!("CC={}", cc));
-        } else if is_cross_compile {
+        } else if is_cross_compile || is_musl {
+            // cc crate selects `musl-gcc` or the target-prefixed compiler,
+            // e.g. `aarch64-linux-musl-gcc`, for musl targets
             let compiler = cc::Build::new().get_compiler();
             let compiler_path = compiler.path().to_str();
             if let Some(cc) = compiler_path {
@@ -481,22 +685,129 @@ impl Configure {
         Ok(args)
     }
 
-    /// Build OpenBLAS
+    /// Fingerprint of the build by this configuration, i.e. [CacheKey::digest]
+    pub fn fingerprint(&self) -> Result<String, Error> {
+        Ok(CacheKey::new(self)?.digest())
+    }
+
+    /// File names of the libraries to be built
+    fn library_names(&self) -> Vec<&'static str> {
+        let mut libs = Vec::new();
+        if !self.no_static {
+            libs.push("libopenblas.a");
+        }
+        if !self.no_shared {
+            let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_else(|_| {
+                if cfg!(target_os = "macos") {
+                    "macos".into()
+                } else {
+                    String::new()
+                }
+            });
+            libs.push(if target_os == "macos" {
+                "libopenblas.dylib"
+            } else {
+                "libopenblas.so"
+            });
+        }
+        libs
+    }
+
+    /// Check that the libraries to be built exist and are not empty
+    fn check_artifacts(&self, root: &Path) -> Result<(), Error> {
+        for lib in self.library_names() {
+            let path = root.join(lib);
+            if !fs::metadata(&path).is_ok_and(|m| m.len() > 0) {
+                return Err(Error::LibraryNotExist { path });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the routines of the requested precisions, e.g. `sgemm_` for single precision,
+    /// are found in the built library by `nm`
+    fn check_precisions(&self, root: &Path) -> Result<(), Error> {
+        let lib = match self.library_names().first() {
+            Some(name) => root.join(name),
+            None => return Ok(()),
+        };
+        let inspect = match LibInspect::new(&lib) {
+            Ok(inspect) => inspect,
+            Err(Error::IOError(e)) => {
+                println!(
+                    "cargo:warning=OpenBLAS: Cannot inspect {} by nm, and precisions are not checked: {}",
+                    lib.display(),
+                    e
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let built = inspect.precisions();
+        let missing: Vec<&str> = [
+            ("single", self.precisions.single, built.single),
+            ("double", self.precisions.double, built.double),
+            ("complex", self.precisions.complex, built.complex),
+            ("complex16", self.precisions.complex16, built.complex16),
+        ]
+        .iter()
+        .filter(|(_, requested, built)| *requested && !*built)
+        .map(|(name, _, _)| *name)
+        .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingPrecisions {
+                precisions: missing.join(", "),
+            })
+        }
+    }
+
+    /// Build OpenBLAS out of the `source` tree in `build_dir`, and install it into `prefix`
     ///
-    /// Libraries are created directly under `out_dir` e.g. `out_dir/libopenblas.a`
+    /// `source` is copied into `build_dir` and never modified,
+    /// so that it can be read-only or shared among builds with different configurations.
+    /// Libraries are created directly under `build_dir` e.g. `build_dir/libopenblas.a`,
+    /// and then installed by `make install` into `prefix/lib` with headers in `prefix/include`.
+    /// `openblas.pc` and `OpenBLASConfig.cmake` are also written into `prefix/lib/pkgconfig`
+    /// and `prefix/lib/cmake/openblas`, see [write_pkg_config] and [write_cmake_config].
+    ///
+    /// The previous build in `build_dir` is reused only when it has [BUILD_STAMP]
+    /// with the same [Configure::fingerprint], which is written after the libraries are built.
+    /// Otherwise, the previous build, e.g. interrupted or built with another configuration,
+    /// is removed and OpenBLAS is rebuilt from `source`.
     ///
     /// Error
     /// -----
+    /// - Routines of the requested [Configure::precisions] are not found in the built library.
     /// - Build deliverables are invalid same as [inspect].
     ///   This means that the system environment is not appropriate to execute `make`,
     ///   e.g. LAPACK is required but there is no Fortran compiler.
     ///
-    pub fn build<P: AsRef<Path>>(self, openblas_root: P) -> Result<MakeConf, Error> {
-        let root = openblas_root.as_ref();
-        // Do not build if libraries and Makefile.conf already exist and are valid
-        if let Ok(make_conf) = MakeConf::new(root.join("Makefile.conf")) {
-            return Ok(make_conf);
+    pub fn build<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+        self,
+        source: P,
+        build_dir: Q,
+        prefix: R,
+    ) -> Result<MakeConf, Error> {
+        let root = build_dir.as_ref();
+        let prefix = prefix.as_ref();
+        let fingerprint = self.fingerprint()?;
+        let stamp = root.join(BUILD_STAMP);
+        // Do not build if the build with the same configuration has been completed
+        if fs::read_to_string(&stamp).is_ok_and(|s| s.trim() == fingerprint)
+            && self.check_artifacts(&prefix.join("lib")).is_ok()
+        {
+            if let Ok(make_conf) = MakeConf::new(root.join("Makefile.conf")) {
+                return Ok(make_conf);
+            }
         }
+        for dir in [root, prefix] {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        copy_tree(source.as_ref(), root)?;
 
         // check if cross compile is needed
         // let build_target = env::var("TARGET").unwrap_or_default();
@@ -507,45 +818,101 @@ impl Configure {
         // }
 
         // Run `make` as an subprocess
-        //
-        // - This will automatically run in parallel without `-j` flag
-        // - The `make` of OpenBLAS outputs 30k lines,
-        //   which will be redirected into `out.log` and `err.log`.
-        // - cargo sets `TARGET` environment variable as target triple (e.g. x86_64-unknown-linux-gnu)
-        //   while binding build.rs, but `make` read it as CPU target specification.
-        //
-        let out = fs::File::create(root.join("out.log")).expect("Cannot create log file");
-        let err = fs::File::create(root.join("err.log")).expect("Cannot create log file");
-        match Command::new("make")
-            .current_dir(root)
-            .stdout(out)
-            .stderr(err)
-            .args(self.make_args()?)
-            .args(["all"])
-            .env_remove("TARGET")
-            .check_call()
-        {
-            Ok(_) => {}
-            Err(err @ Error::NonZeroExitStatus { .. }) => {
-                eprintln!(
-                    "{}",
-                    fs::read_to_string(root.join("err.log")).expect("Cannot read log file")
-                );
-                return Err(err);
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
-
+        let make_args = self.make_args()?;
+        run_make(root, &make_args, "all")?;
         let make_conf = MakeConf::new(root.join("Makefile.conf"))?;
         if make_conf.no_fortran {
             println!("cargo:warning=OpenBLAS: Detecting fortran compiler failed. Only BLAS and f2c-converted LAPACK are compiled.");
         }
+        self.check_artifacts(root)?;
+        self.check_precisions(root)?;
+
+        // Install headers and libraries into `prefix/include` and `prefix/lib`
+        let mut install_args = make_args;
+        install_args.push(format!("PREFIX={}", prefix.display()));
+        run_make(root, &install_args, "install")?;
+        self.check_artifacts(&prefix.join("lib"))?;
+        // Replace the files installed by OpenBLAS to include the libraries required for static linking
+        let openmp = self.threading == Threading::OpenMP;
+        write_pkg_config(prefix, &self.version, &make_conf, openmp)?;
+        write_cmake_config(prefix, &self.version, &make_conf, openmp)?;
+
+        // Write the stamp atomically not to leave a partially written one
+        let tmp = root.join(format!("{}.tmp", BUILD_STAMP));
+        fs::write(&tmp, format!("{}\n", fingerprint))?;
+        fs::rename(&tmp, &stamp)?;
         Ok(make_conf)
     }
 }
 
+/// Run `make {args} {target}` in `root`
+///
+/// - This will automatically run in parallel without `-j` flag
+/// - The `make` of OpenBLAS outputs 30k lines,
+///   which will be appended into `out.log` and `err.log`.
+/// - cargo sets `TARGET` environment variable as target triple (e.g. x86_64-unknown-linux-gnu)
+///   while binding build.rs, but `make` read it as CPU target specification.
+/// - Variables specified by `Configure` are also removed from the environment
+///   not to be overridden by the user's shell.
+fn run_make(root: &Path, args: &[String], target: &str) -> Result<(), Error> {
+    let log = |name: &str| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(root.join(name))
+            .expect("Cannot create log file")
+    };
+    let mut make = Command::new("make");
+    make.current_dir(root)
+        .stdout(log("out.log"))
+        .stderr(log("err.log"))
+        .args(args)
+        .arg(target)
+        .env_remove("TARGET");
+    for var in CONFIGURE_VARIABLES {
+        make.env_remove(var);
+    }
+    match make.check_call() {
+        Ok(_) => Ok(()),
+        Err(err @ Error::NonZeroExitStatus { .. }) => {
+            eprintln!(
+                "{}",
+                fs::read_to_string(root.join("err.log")).expect("Cannot read log file")
+            );
+            Err(err)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Copy the directory tree `src` into `dest`, making copied files writable
+fn copy_tree(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&from, &to)?;
+            continue;
+        }
+        #[cfg(unix)]
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&from)?, &to)?;
+            continue;
+        }
+        fs::copy(&from, &to)?;
+        let mut permissions = fs::metadata(&to)?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            fs::set_permissions(&to, permissions)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -559,23 +926,181 @@ mod tests {
         ));
     }
 
-    fn get_openblas_source<P: AsRef<Path>>(out_dir: P) -> PathBuf {
-        let openblas_src_root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../openblas-src/");
-        let source = crate::download(&openblas_src_root).unwrap();
-        // copy files to the target directory
-        let out_dir = out_dir.as_ref();
-        fs::create_dir_all(out_dir).unwrap();
-        for entry in walkdir::WalkDir::new(&source) {
-            let entry = entry.unwrap();
-            let src = entry.path();
-            let dest = out_dir.join(src.strip_prefix(&source).unwrap());
-            if entry.file_type().is_dir() {
-                fs::create_dir_all(&dest).unwrap();
-            } else {
-                fs::copy(src, dest).unwrap();
-            }
+    #[test]
+    fn make_args_threading() {
+        let opt = Configure {
+            threading: Threading::Serial,
+            ..Default::default()
+        };
+        let args = opt.make_args().unwrap();
+        assert!(args.contains(&"USE_THREAD=0".to_string()));
+        assert!(!args.contains(&"USE_OPENMP=1".to_string()));
+
+        let opt = Configure {
+            threading: Threading::OpenMP,
+            ..Default::default()
+        };
+        let args = opt.make_args().unwrap();
+        assert!(args.contains(&"USE_THREAD=1".to_string()));
+        assert!(args.contains(&"USE_OPENMP=1".to_string()));
+        assert!(!args.contains(&"USE_THREAD=0".to_string()));
+
+        let args = Configure::default().make_args().unwrap();
+        assert!(!args.iter().any(|arg| arg.starts_with("USE_THREAD=")));
+    }
+
+    #[test]
+    fn make_args_dynamic_arch() {
+        let opt = Configure {
+            dynamic_arch: true,
+            dynamic_list: vec![Target::HASWELL, Target::SKYLAKEX],
+            ..Default::default()
+        };
+        let args = opt.make_args().unwrap();
+        assert!(args.contains(&"DYNAMIC_ARCH=1".to_string()));
+        assert!(args.contains(&"DYNAMIC_LIST=HASWELL SKYLAKEX".to_string()));
+    }
+
+    #[test]
+    fn make_args_options() {
+        let opt = Configure {
+            num_threads: Some(64),
+            buffer_size: Some(25),
+            no_affinity: Some(true),
+            small_matrix_opt: Some(false),
+            ..Default::default()
+        };
+        let args = opt.make_args().unwrap();
+        assert!(args.contains(&"NUM_THREADS=64".to_string()));
+        assert!(args.contains(&"BUFFERSIZE=25".to_string()));
+        assert!(args.contains(&"NO_AFFINITY=1".to_string()));
+        assert!(args.contains(&"SMALL_MATRIX_OPT=0".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("USE_TLS")));
+    }
+
+    #[test]
+    fn make_args_precisions() {
+        let mut opt = Configure::default();
+        assert!(!opt
+            .make_args()
+            .unwrap()
+            .iter()
+            .any(|arg| arg.starts_with("BUILD_")));
+
+        opt.precisions = Precisions {
+            single: true,
+            double: false,
+            complex: true,
+            complex16: false,
+        };
+        let args = opt.make_args().unwrap();
+        assert!(args.contains(&"BUILD_SINGLE=1".to_string()));
+        assert!(args.contains(&"BUILD_COMPLEX=1".to_string()));
+        assert!(!args.contains(&"BUILD_DOUBLE=1".to_string()));
+
+        opt.precisions.single = false;
+        opt.precisions.complex = false;
+        assert!(matches!(
+            opt.make_args().unwrap_err(),
+            crate::error::Error::NoPrecision
+        ));
+    }
+
+    #[test]
+    fn check_precisions() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build/check_precisions");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Library which has only single and double precision routines
+        fs::write(
+            dir.join("gemm.c"),
+            "void sgemm_(void) {}\nvoid dgemm_(void) {}\n",
+        )
+        .unwrap();
+        Command::new("cc")
+            .current_dir(&dir)
+            .args(["-c", "gemm.c", "-o", "gemm.o"])
+            .check_call()
+            .unwrap();
+        Command::new("ar")
+            .current_dir(&dir)
+            .args(["rcs", "libopenblas.a", "gemm.o"])
+            .check_call()
+            .unwrap();
+
+        let cfg = Configure {
+            no_shared: true,
+            precisions: Precisions {
+                single: true,
+                double: true,
+                complex: false,
+                complex16: false,
+            },
+            ..Default::default()
+        };
+        cfg.check_precisions(&dir).unwrap();
+
+        let cfg = Configure {
+            no_shared: true,
+            ..Default::default()
+        };
+        match cfg.check_precisions(&dir).unwrap_err() {
+            Error::MissingPrecisions { precisions } => assert_eq!(precisions, "complex, complex16"),
+            e => panic!("Unexpected error: {}", e),
         }
-        out_dir.to_path_buf()
+    }
+
+    #[test]
+    fn build_stamp() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let source = root.join("test_build/build_stamp/source");
+        let build_dir = root.join("test_build/build_stamp/build");
+        let _ = fs::remove_dir_all(root.join("test_build/build_stamp"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&build_dir).unwrap();
+        // Makefile without `all` target, which fails if OpenBLAS is rebuilt
+        fs::write(source.join("Makefile"), "clean:\n").unwrap();
+        fs::copy(root.join("Makefile.conf"), build_dir.join("Makefile.conf")).unwrap();
+
+        // Completed build with the same configuration is reused
+        let cfg = Configure::default();
+        fs::write(build_dir.join(BUILD_STAMP), cfg.fingerprint().unwrap()).unwrap();
+        let prefix = root.join("test_build/build_stamp/prefix");
+        fs::create_dir_all(prefix.join("lib")).unwrap();
+        for lib in ["libopenblas.a", "libopenblas.so", "libopenblas.dylib"] {
+            fs::write(prefix.join("lib").join(lib), "dummy").unwrap();
+        }
+        assert!(cfg.clone().build(&source, &build_dir, &prefix).is_ok());
+
+        // Stamp of another configuration triggers rebuild
+        let other = Configure {
+            no_lapacke: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            other.build(&source, &build_dir, &prefix).unwrap_err(),
+            Error::NonZeroExitStatus { .. }
+        ));
+        assert!(!build_dir.join(BUILD_STAMP).exists());
+        // Build runs in `build_dir`, and source is not modified
+        assert!(build_dir.join("Makefile").exists());
+        assert!(build_dir.join("out.log").exists());
+        assert_eq!(fs::read_dir(&source).unwrap().count(), 1);
+
+        // Interrupted build without stamp is not reused
+        assert!(cfg.build(&source, &build_dir, &prefix).is_err());
+    }
+
+    fn get_openblas_source() -> PathBuf {
+        let openblas_src_root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../openblas-src/");
+        crate::download(
+            &crate::Source::default(),
+            OPENBLAS_VERSION,
+            &openblas_src_root,
+            None,
+            &crate::Network::default(),
+        )
+        .unwrap()
     }
 
     #[ignore]
@@ -584,7 +1109,12 @@ mod tests {
         let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         let out_dir = root.join("test_build/build_default");
         let opt = Configure::default();
-        let _ = opt.build(get_openblas_source(&out_dir)).unwrap();
+        let prefix = out_dir.join("prefix");
+        let _ = opt.build(get_openblas_source(), &out_dir, &prefix).unwrap();
+        for header in ["cblas.h", "f77blas.h", "lapacke.h", "openblas_config.h"] {
+            assert!(prefix.join("include").join(header).exists());
+        }
+        let _ = LibInspect::new(prefix.join("lib/libopenblas.a")).unwrap();
     }
 
     #[ignore]
@@ -594,7 +1124,8 @@ mod tests {
         let out_dir = root.join("test_build/build_no_shared");
         let mut opt = Configure::default();
         opt.no_shared = true;
-        opt.build(get_openblas_source(&out_dir)).unwrap();
+        opt.build(get_openblas_source(), &out_dir, out_dir.join("prefix"))
+            .unwrap();
         let _ = LibInspect::new(out_dir.join("libopenblas.a")).unwrap();
     }
 
@@ -605,7 +1136,9 @@ mod tests {
         let out_dir = root.join("test_build/build_no_lapacke");
         let mut opt = Configure::default();
         opt.no_lapacke = true;
-        let _ = opt.build(get_openblas_source(&out_dir)).unwrap();
+        let _ = opt
+            .build(get_openblas_source(), &out_dir, out_dir.join("prefix"))
+            .unwrap();
         let lib_name = if cfg!(target_os = "macos") {
             "libopenblas.dylib"
         } else {
@@ -624,7 +1157,9 @@ mod tests {
         let out_dir = root.join("test_build/build_no_cblas");
         let mut opt = Configure::default();
         opt.no_lapacke = true;
-        let _ = opt.build(get_openblas_source(&out_dir)).unwrap();
+        let _ = opt
+            .build(get_openblas_source(), &out_dir, out_dir.join("prefix"))
+            .unwrap();
         let lib_name = if cfg!(target_os = "macos") {
             "libopenblas.dylib"
         } else {
@@ -640,9 +1175,13 @@ mod tests {
     fn build_openmp() {
         let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         let out_dir = root.join("test_build/build_openmp");
-        let mut opt = Configure::default();
-        opt.use_openmp = true;
-        let _ = opt.build(get_openblas_source(&out_dir)).unwrap();
+        let opt = Configure {
+            threading: Threading::OpenMP,
+            ..Default::default()
+        };
+        let _ = opt
+            .build(get_openblas_source(), &out_dir, out_dir.join("prefix"))
+            .unwrap();
         let lib_name = if cfg!(target_os = "macos") {
             "libopenblas.dylib"
         } else {
@@ -651,4 +1190,26 @@ mod tests {
         let lib_inspect = LibInspect::new(out_dir.join(lib_name)).unwrap();
         assert!(lib_inspect.has_lib("gomp"));
     }
+
+    #[ignore]
+    #[test]
+    fn build_single_only() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let out_dir = root.join("test_build/build_single_only");
+        let opt = Configure {
+            no_shared: true,
+            precisions: Precisions {
+                single: true,
+                double: false,
+                complex: false,
+                complex16: false,
+            },
+            ..Default::default()
+        };
+        let precisions = opt.precisions;
+        opt.build(get_openblas_source(), &out_dir, out_dir.join("prefix"))
+            .unwrap();
+        let lib_inspect = LibInspect::new(out_dir.join("libopenblas.a")).unwrap();
+        assert_eq!(lib_inspect.precisions(), precisions);
+    }
 }
@@ -0,0 +1,434 @@
+use crate::{error::*, Configure, MakeConf, BUILD_STAMP};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+/// Version of [CacheKey] format, which is incremented when its contents are changed
+pub const CACHE_KEY_VERSION: u32 = 2;
+
+/// Environment variables included in [CacheKey]
+///
+/// They affect the build but are not reflected in the arguments of `make`,
+/// i.e. compilers and flags read by `make` of OpenBLAS, build options of `Makefile.rule`
+/// which are not overridden by [Configure], and variables of openblas-src specifying
+/// the source of OpenBLAS. Other variables still reach `make` but are not included.
+/// Other `OPENBLAS_*` variables of openblas-src are reflected through [Configure],
+/// and runtime variables of OpenBLAS, e.g. `OPENBLAS_NUM_THREADS`, do not affect the build.
+pub const CACHE_KEY_VARIABLES: &[&str] = &[
+    "CC",
+    "FC",
+    "HOSTCC",
+    "RANLIB",
+    "CFLAGS",
+    "FFLAGS",
+    "LDFLAGS",
+    "COMMON_OPT",
+    "FCOMMON_OPT",
+    "BINARY",
+    "TARGET_CORE",
+    "DYNAMIC_OLDER",
+    "NOFORTRAN",
+    "C_LAPACK",
+    "ONLY_CBLAS",
+    "BUILD_LAPACK_DEPRECATED",
+    "LAPACK_STRLEN",
+    "USE_SIMPLE_THREADED_LEVEL3",
+    "NO_AVX",
+    "NO_CPUID",
+    "NO_WARMUP",
+    "BIGNUMA",
+    "EXPRECISION",
+    "QUAD_PRECISION",
+    "SYMBOLPREFIX",
+    "SYMBOLSUFFIX",
+    "LIBNAMEPREFIX",
+    "LIBNAMESUFFIX",
+    "DEBUG",
+    "OPENBLAS_SOURCE_DIR",
+    "OPENBLAS_SOURCE_TARBALL",
+    "OPENBLAS_SOURCE_SHA256",
+];
+
+/// Root directory of the builds shared by `cache` feature of openblas-src
+///
+/// This is `openblas_build` under the user's data directory,
+/// e.g. `$XDG_DATA_HOME/openblas_build` or `$HOME/.local/share/openblas_build` on Linux.
+/// See [dirs::data_dir] for other platforms.
+pub fn cache_root() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("openblas_build"))
+}
+
+/// Inputs of the build which affect its artifacts
+///
+/// The cached build is placed in `{cache_root}/{digest}` where `digest` is [CacheKey::digest],
+/// i.e. SHA-256 of [CacheKey::to_json], which is stable across Rust toolchains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    /// Version of OpenBLAS
+    pub version: String,
+    /// Target triple of cargo, e.g. `x86_64-unknown-linux-gnu`
+    pub target: String,
+    /// Host triple of cargo
+    pub host: String,
+    /// Arguments of `make` generated by [Configure]
+    pub make_args: Vec<String>,
+    /// First line of `--version` of C and Fortran compilers
+    pub compilers: BTreeMap<String, String>,
+    /// Environment variables affecting the build, i.e. [CACHE_KEY_VARIABLES] which are set
+    pub env: BTreeMap<String, String>,
+}
+
+impl CacheKey {
+    /// Collect the key of the build by `cfg` from its `make` arguments, compilers and environment
+    pub fn new(cfg: &Configure) -> Result<Self, Error> {
+        let make_args = cfg.make_args()?;
+        let make_var = |name: &str| {
+            let prefix = format!("{}=", name);
+            make_args
+                .iter()
+                .find_map(|arg| arg.strip_prefix(&prefix).map(|value| value.to_string()))
+                .or_else(|| env::var(name).ok())
+        };
+
+        let mut compilers = BTreeMap::new();
+        let cc = make_var("CC").unwrap_or_else(|| "cc".into());
+        compilers.insert(cc.clone(), compiler_version(&cc));
+        if !make_args.iter().any(|arg| arg == "NOFORTRAN=1") {
+            let fc = make_var("FC").unwrap_or_else(|| "gfortran".into());
+            compilers.insert(fc.clone(), compiler_version(&fc));
+        }
+
+        let env = CACHE_KEY_VARIABLES
+            .iter()
+            .filter_map(|name| Some((name.to_string(), env::var(name).ok()?)))
+            .collect();
+
+        Ok(CacheKey {
+            version: cfg.version.clone(),
+            target: env::var("TARGET").unwrap_or_default(),
+            host: env::var("HOST").unwrap_or_default(),
+            make_args,
+            compilers,
+            env,
+        })
+    }
+
+    /// Canonical JSON representation, written as `key.json` in the build directory
+    pub fn to_json(&self) -> String {
+        let list = |values: &[String]| {
+            values
+                .iter()
+                .map(|value| format!("\n    {}", json_string(value)))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let map = |values: &BTreeMap<String, String>| {
+            values
+                .iter()
+                .map(|(key, value)| format!("\n    {}: {}", json_string(key), json_string(value)))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "{{\n  \"key_version\": {},\n  \"version\": {},\n  \"target\": {},\n  \"host\": {},\n  \"make_args\": [{}\n  ],\n  \"compilers\": {{{}\n  }},\n  \"env\": {{{}\n  }}\n}}\n",
+            CACHE_KEY_VERSION,
+            json_string(&self.version),
+            json_string(&self.target),
+            json_string(&self.host),
+            list(&self.make_args),
+            map(&self.compilers),
+            map(&self.env),
+        )
+    }
+
+    /// SHA-256 of [CacheKey::to_json] as hex string
+    pub fn digest(&self) -> String {
+        sha256_hex(self.to_json().as_bytes())
+    }
+}
+
+/// A build directory `{cache_root}/{digest}` in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+}
+
+impl CacheEntry {
+    /// List builds in `cache_root`, sorted by their digests
+    ///
+    /// `sources` directory for source tarballs is not included.
+    pub fn list<P: AsRef<Path>>(cache_root: P) -> io::Result<Vec<Self>> {
+        let cache_root = cache_root.as_ref();
+        if !cache_root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(cache_root)? {
+            let path = entry?.path();
+            if path.is_dir() && path.file_name().is_some_and(|name| name != "sources") {
+                entries.push(CacheEntry { path });
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Name of the directory, i.e. [CacheKey::digest]
+    pub fn digest(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Contents of `key.json`
+    pub fn key_json(&self) -> io::Result<String> {
+        fs::read_to_string(self.path.join("key.json"))
+    }
+
+    /// Version of OpenBLAS in `key.json`, e.g. `0.3.31`
+    ///
+    /// This is known even if the source is not extracted into [CacheEntry::source_root],
+    /// e.g. built from `OPENBLAS_SOURCE_DIR`.
+    pub fn version(&self) -> Option<String> {
+        let json = self.key_json().ok()?;
+        json.lines().find_map(|line| {
+            let value = line.trim().strip_prefix("\"version\": ")?;
+            Some(value.trim_end_matches(',').trim_matches('"').to_string())
+        })
+    }
+
+    /// Root directory of OpenBLAS source extracted from the tarball, e.g. `OpenBLAS-0.3.31`,
+    /// which is copied into [CacheEntry::build_dir] to be built
+    pub fn source_root(&self) -> Option<PathBuf> {
+        fs::read_dir(&self.path).ok()?.find_map(|entry| {
+            let path = entry.ok()?.path();
+            let is_source = path.is_dir() && path.file_name()?.to_str()?.starts_with("OpenBLAS-");
+            if is_source {
+                Some(path)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Directory where OpenBLAS is built out of [CacheEntry::source_root]
+    pub fn build_dir(&self) -> PathBuf {
+        self.path.join("build")
+    }
+
+    /// Prefix where OpenBLAS is installed into, i.e. `include/` and `lib/`
+    pub fn install_dir(&self) -> PathBuf {
+        self.path.join("install")
+    }
+
+    /// Last time when the build script used this build, i.e. modified time of `key.json`
+    pub fn last_used(&self) -> Option<SystemTime> {
+        fs::metadata(self.path.join("key.json"))
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// Total size of files in bytes
+    pub fn size(&self) -> u64 {
+        fn dir_size(path: &Path) -> u64 {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(_) => return 0,
+            };
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| match entry.file_type() {
+                    Ok(t) if t.is_dir() => dir_size(&entry.path()),
+                    Ok(_) => entry.metadata().map_or(0, |m| m.len()),
+                    Err(_) => 0,
+                })
+                .sum()
+        }
+        dir_size(&self.path)
+    }
+
+    /// Check that the build has been completed and is consistent with its key
+    ///
+    /// - SHA-256 of `key.json` matches the directory name,
+    /// - [BUILD_STAMP] contains the same digest,
+    /// - `Makefile.conf` can be parsed, and libraries are installed.
+    pub fn verify(&self) -> Result<MakeConf, Error> {
+        let digest = self.digest();
+        let key_json = self.key_json()?;
+        let actual = sha256_hex(key_json.as_bytes());
+        if actual != digest {
+            return Err(Error::ChecksumMismatch {
+                path: self.path.join("key.json"),
+                expected: digest,
+                actual,
+            });
+        }
+        let build_dir = self.build_dir();
+        let stamp = fs::read_to_string(build_dir.join(BUILD_STAMP)).unwrap_or_default();
+        if stamp.trim() != digest {
+            return Err(Error::ChecksumMismatch {
+                path: build_dir.join(BUILD_STAMP),
+                expected: digest,
+                actual: stamp.trim().to_string(),
+            });
+        }
+        let make_conf = MakeConf::new(build_dir.join("Makefile.conf"))?;
+        let has_lib = ["libopenblas.a", "libopenblas.so", "libopenblas.dylib"]
+            .iter()
+            .any(|lib| self.install_dir().join("lib").join(lib).exists());
+        if !has_lib {
+            return Err(Error::LibraryNotExist {
+                path: self.install_dir().join("lib/libopenblas.a"),
+            });
+        }
+        Ok(make_conf)
+    }
+
+    /// Remove the build directory
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_dir_all(&self.path)
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// First line of `{compiler} --version`, or empty if it cannot be executed
+fn compiler_version(compiler: &str) -> String {
+    Command::new(compiler)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        })
+        .unwrap_or_default()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> CacheKey {
+        CacheKey {
+            version: "0.3.31".into(),
+            target: "x86_64-unknown-linux-gnu".into(),
+            host: "x86_64-unknown-linux-gnu".into(),
+            make_args: vec!["NO_SHARED=1".into(), "CC=gcc".into()],
+            compilers: [("gcc".to_string(), "gcc (GCC) 14.2.0".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            env: [("CFLAGS".to_string(), "-O2 \"-DX\"".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn cache_key_json() {
+        assert_eq!(
+            key().to_json(),
+            r#"{
+  "key_version": 2,
+  "version": "0.3.31",
+  "target": "x86_64-unknown-linux-gnu",
+  "host": "x86_64-unknown-linux-gnu",
+  "make_args": [
+    "NO_SHARED=1",
+    "CC=gcc"
+  ],
+  "compilers": {
+    "gcc": "gcc (GCC) 14.2.0"
+  },
+  "env": {
+    "CFLAGS": "-O2 \"-DX\""
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn cache_key_digest() {
+        let digest = key().digest();
+        assert_eq!(digest.len(), 64);
+        assert_eq!(digest, key().digest());
+        let mut other = key();
+        other.version = "0.3.30".into();
+        assert_ne!(digest, other.digest());
+    }
+
+    #[test]
+    fn cache_entry() {
+        let cache_root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build/cache_entry");
+        let _ = fs::remove_dir_all(&cache_root);
+        let key = key();
+        let path = cache_root.join(key.digest());
+        let source_root = path.join("OpenBLAS-0.3.31");
+        let build_dir = path.join("build");
+        fs::create_dir_all(&source_root).unwrap();
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::create_dir_all(cache_root.join("sources")).unwrap();
+        fs::write(path.join("key.json"), key.to_json()).unwrap();
+        fs::copy(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Makefile.conf"),
+            build_dir.join("Makefile.conf"),
+        )
+        .unwrap();
+
+        let entries = CacheEntry::list(&cache_root).unwrap();
+        assert_eq!(entries, vec![CacheEntry { path: path.clone() }]);
+        let entry = &entries[0];
+        assert_eq!(entry.digest(), key.digest());
+        assert_eq!(entry.version().as_deref(), Some("0.3.31"));
+        assert_eq!(entry.source_root(), Some(source_root.clone()));
+        assert!(entry.last_used().is_some());
+        assert!(entry.size() > 0);
+
+        // Interrupted build without stamp
+        assert!(entry.verify().is_err());
+        fs::write(build_dir.join(BUILD_STAMP), key.digest()).unwrap();
+        assert!(matches!(
+            entry.verify().unwrap_err(),
+            Error::LibraryNotExist { .. }
+        ));
+        fs::create_dir_all(path.join("install/lib")).unwrap();
+        fs::write(path.join("install/lib/libopenblas.a"), "dummy").unwrap();
+        assert!(entry.verify().is_ok());
+
+        entry.remove().unwrap();
+        assert!(CacheEntry::list(&cache_root).unwrap().is_empty());
+    }
+}
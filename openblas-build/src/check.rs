@@ -2,7 +2,7 @@
 
 use crate::error::*;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs,
     hash::Hash,
     io::{self, BufRead},
@@ -63,6 +63,16 @@ impl LinkFlags {
 #[derive(Debug, Clone, Default)]
 pub struct MakeConf {
     pub os_name: String,
+    /// Compiler family detected by OpenBLAS, e.g. `GCC` or `CLANG`
+    pub c_compiler: String,
+    /// Fortran compiler used by OpenBLAS, e.g. `gfortran`
+    pub fc: String,
+    /// Target CPU, e.g. `HASWELL`
+    pub core: String,
+    /// Name of target CPU used in the library name, e.g. `haswell`
+    pub libcore: String,
+    /// Number of CPU cores of the build host
+    pub num_cores: Option<u32>,
     pub no_fortran: bool,
     pub c_extra_libs: LinkFlags,
     pub f_extra_libs: LinkFlags,
@@ -87,6 +97,11 @@ impl MakeConf {
             }
             match entry[0] {
                 "OSNAME" => detail.os_name = entry[1].into(),
+                "C_COMPILER" => detail.c_compiler = entry[1].into(),
+                "FC" => detail.fc = entry[1].into(),
+                "CORE" => detail.core = entry[1].into(),
+                "LIBCORE" => detail.libcore = entry[1].into(),
+                "NUM_CORES" => detail.num_cores = entry[1].trim().parse().ok(),
                 "NOFORTRAN" => detail.no_fortran = true,
                 "CEXTRALIB" => detail.c_extra_libs = LinkFlags::parse(entry[1])?,
                 "FEXTRALIB" => detail.f_extra_libs = LinkFlags::parse(entry[1])?,
@@ -97,6 +112,66 @@ impl MakeConf {
         }
         Ok(detail)
     }
+
+    /// OpenMP runtime library of the C compiler, e.g. `gomp` for GCC,
+    /// which is not listed in `CEXTRALIB` and `FEXTRALIB`
+    pub fn openmp_lib(&self) -> &'static str {
+        match self.c_compiler.as_str() {
+            "CLANG" => "omp",
+            "INTEL" => "iomp5",
+            _ => "gomp",
+        }
+    }
+}
+
+/// Parse `openblas_config.h` installed with OpenBLAS headers
+///
+/// This is used to inspect OpenBLAS which is not built by this crate, e.g. found by pkg-config.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigHeader {
+    /// Macros defined as `#define OPENBLAS_{name} {value}` with `OPENBLAS_` prefix removed
+    pub defines: BTreeMap<String, String>,
+}
+
+impl ConfigHeader {
+    /// Parse from file
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut defines = BTreeMap::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("#define") {
+                continue;
+            }
+            if let Some(name) = words.next().and_then(|name| name.strip_prefix("OPENBLAS_")) {
+                defines.insert(name.to_string(), words.collect::<Vec<_>>().join(" "));
+            }
+        }
+        Ok(ConfigHeader { defines })
+    }
+
+    /// Version of OpenBLAS, e.g. `0.3.31`
+    pub fn version(&self) -> Option<&str> {
+        // Defined as `" OpenBLAS 0.3.31 "`
+        self.defines
+            .get("VERSION")?
+            .trim_matches(|c: char| c == '"' || c.is_whitespace())
+            .strip_prefix("OpenBLAS ")
+    }
+
+    /// Target CPU, e.g. `HASWELL`
+    pub fn core(&self) -> Option<&str> {
+        Some(self.defines.get("CHAR_CORENAME")?.trim_matches('"'))
+    }
+
+    /// Built with 64-bit integer interface (`INTERFACE64=1`)
+    pub fn ilp64(&self) -> bool {
+        self.defines.contains_key("USE64BITINT")
+    }
+
+    /// Built with OpenMP (`USE_OPENMP=1`)
+    pub fn openmp(&self) -> bool {
+        self.defines.contains_key("USE_OPENMP")
+    }
 }
 
 /// Library inspection using binutils (`nm` and `objdump`) as external command
@@ -104,13 +179,12 @@ impl MakeConf {
 /// - Linked shared libraries using `objdump -p` external command.
 /// - Global "T" symbols in the text (code) section of library using `nm -g` external command.
 #[derive(Debug, Clone)]
-#[cfg(test)]
 pub(crate) struct LibInspect {
+    #[cfg_attr(not(test), allow(dead_code))]
     pub libs: Vec<String>,
     pub symbols: Vec<String>,
 }
 
-#[cfg(test)]
 impl LibInspect {
     /// Inspect library file
     ///
@@ -166,6 +240,7 @@ impl LibInspect {
         Ok(LibInspect { libs, symbols })
     }
 
+    #[cfg(test)]
     pub fn has_cblas(&self) -> bool {
         for sym in &self.symbols {
             if sym.starts_with("cblas_") {
@@ -175,6 +250,7 @@ impl LibInspect {
         false
     }
 
+    #[cfg(test)]
     pub fn has_lapack(&self) -> bool {
         for sym in &self.symbols {
             if sym == "dsyev_" {
@@ -184,6 +260,7 @@ impl LibInspect {
         false
     }
 
+    #[cfg(test)]
     pub fn has_lapacke(&self) -> bool {
         for sym in &self.symbols {
             if sym.starts_with("LAPACKE_") {
@@ -193,6 +270,23 @@ impl LibInspect {
         false
     }
 
+    /// Precisions whose GEMM routine, e.g. `sgemm_`, is found in the library
+    pub fn precisions(&self) -> crate::Precisions {
+        // Symbols have a leading underscore on macOS, e.g. `_sgemm_`
+        let has_symbol = |name: &str| {
+            self.symbols
+                .iter()
+                .any(|sym| sym == name || sym.strip_prefix('_') == Some(name))
+        };
+        crate::Precisions {
+            single: has_symbol("sgemm_"),
+            double: has_symbol("dgemm_"),
+            complex: has_symbol("cgemm_"),
+            complex16: has_symbol("zgemm_"),
+        }
+    }
+
+    #[cfg(test)]
     pub fn has_lib(&self, name: &str) -> bool {
         for lib in &self.libs {
             if let Some(stem) = lib.split('.').next() {
@@ -214,5 +308,35 @@ mod tests {
         assert!(path.exists());
         let detail = MakeConf::new(path).unwrap();
         assert!(!detail.no_fortran);
+        assert_eq!(detail.c_compiler, "GCC");
+        assert_eq!(detail.fc, "gfortran");
+        assert_eq!(detail.core, "HASWELL");
+        assert_eq!(detail.libcore, "haswell");
+        assert_eq!(detail.num_cores, Some(12));
+    }
+
+    #[test]
+    fn config_header() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("openblas_config.h");
+        fs::write(
+            &path,
+            r#"#ifndef OPENBLAS_CONFIG_H
+#define OPENBLAS_CONFIG_H
+#define OPENBLAS_OS_LINUX	1
+#define OPENBLAS_HASWELL
+#define OPENBLAS_CHAR_CORENAME	"HASWELL"
+#define OPENBLAS_USE64BITINT
+#define OPENBLAS_VERSION " OpenBLAS 0.3.31 "
+#endif
+"#,
+        )
+        .unwrap();
+        let header = ConfigHeader::new(path).unwrap();
+        assert_eq!(header.version(), Some("0.3.31"));
+        assert_eq!(header.core(), Some("HASWELL"));
+        assert!(header.ilp64());
+        assert!(!header.openmp());
     }
 }
@@ -1,37 +1,372 @@
+use crate::{error::Error, lock::BuildLock};
 use anyhow::Result;
-use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 use ureq::{
     config::Config,
     tls::{TlsConfig, TlsProvider},
 };
 
-const OPENBLAS_VERSION: &str = "0.3.31";
+/// Default version of OpenBLAS
+pub const OPENBLAS_VERSION: &str = "0.3.31";
+
+/// Known versions of OpenBLAS and SHA-256 of their release tarballs `OpenBLAS-{version}.tar.gz`
+///
+/// Every version listed here must have the checksum of its release asset,
+/// e.g. by `sha256sum OpenBLAS-0.3.31.tar.gz`, which the tests check.
+/// `None` only marks a version whose checksum is still to be filled in,
+/// and such a version cannot be downloaded without the checksum given explicitly,
+/// see [openblas_source_sha256].
+pub const OPENBLAS_VERSIONS: &[(&str, Option<&str>)] = &[("0.3.31", None)];
 
-pub fn openblas_source_url() -> String {
+pub fn openblas_source_url(version: &str) -> String {
     format!(
         "https://github.com/OpenMathLib/OpenBLAS/releases/download/v{}/OpenBLAS-{}.tar.gz",
-        OPENBLAS_VERSION, OPENBLAS_VERSION
+        version, version
+    )
+}
+
+/// Pinned SHA-256 of the release tarball
+///
+/// Error
+/// -----
+/// - `version` is not listed in [OPENBLAS_VERSIONS]
+/// - No checksum is pinned for `version`
+///
+pub fn openblas_source_sha256(version: &str) -> Result<&'static str, Error> {
+    let (_, sha256) = OPENBLAS_VERSIONS
+        .iter()
+        .find(|(v, _)| *v == version)
+        .ok_or_else(|| Error::UnknownVersion {
+            version: version.to_string(),
+        })?;
+    sha256.ok_or_else(|| Error::ChecksumNotPinned {
+        version: version.to_string(),
+    })
+}
+
+/// Where OpenBLAS source is obtained from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// Download a tarball from URLs tried in turn.
+    /// `https://`, `http://` and `file://` URLs are supported.
+    Urls(Vec<String>),
+    /// Local tarball, e.g. `OpenBLAS-0.3.31.tar.gz`
+    Tarball(PathBuf),
+    /// Already extracted source tree, used as it is
+    Directory(PathBuf),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Urls(vec![openblas_source_url(OPENBLAS_VERSION)])
+    }
+}
+
+/// Network settings for downloading OpenBLAS source
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Network {
+    /// Timeout for connecting to a server
+    pub connect_timeout: Duration,
+    /// Timeout for each download attempt
+    pub timeout: Duration,
+    /// Number of retries for each URL after the first attempt fails
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each retry
+    pub backoff: Duration,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Network {
+            connect_timeout: Duration::from_secs(30),
+            timeout: Duration::from_secs(600),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// `CARGO_NET_OFFLINE` is set by `--offline` or `net.offline` configuration of cargo
+fn is_offline() -> bool {
+    matches!(
+        std::env::var("CARGO_NET_OFFLINE").as_deref(),
+        Ok("true") | Ok("1")
     )
 }
 
-pub fn download(out_dir: &Path) -> Result<PathBuf> {
-    let dest = out_dir.join(format!("OpenBLAS-{}", OPENBLAS_VERSION));
-    if !dest.exists() {
-        let buf = get_agent()
-            .get(&openblas_source_url())
-            .call()?
-            .into_body()
-            .into_reader();
-        let gz_stream = flate2::read::GzDecoder::new(buf);
-        let mut ar = tar::Archive::new(gz_stream);
-        ar.unpack(out_dir)?;
-        assert!(dest.exists());
+/// Get OpenBLAS source tree of `version` from `source`, and returns its root directory
+///
+/// - Tarballs are extracted into `out_dir` only when its SHA-256
+///   matches `sha256`, or the pinned one in [OPENBLAS_VERSIONS] if `sha256` is `None`.
+///   It is an error if neither of them is available, see [openblas_source_sha256].
+/// - Download is retried following `network`, and resumed from the partially downloaded file.
+/// - Tarballs are extracted into a temporary directory, and moved to `out_dir` at once
+///   so that a partially extracted tree is never used.
+/// - The checksum of the extracted tarball is recorded in `OpenBLAS-{version}.sha256`,
+///   and the tree is extracted again when another tarball, e.g. a patched one, is given.
+pub fn download(
+    source: &Source,
+    version: &str,
+    out_dir: &Path,
+    sha256: Option<&str>,
+    network: &Network,
+) -> Result<PathBuf> {
+    if let Source::Directory(dir) = source {
+        return Ok(dir.clone());
+    }
+    // Unknown version is accepted only with user's checksum
+    let expected = match sha256 {
+        Some(sha256) => sha256,
+        None => openblas_source_sha256(version)?,
+    };
+    let expected = expected.to_ascii_lowercase();
+    let dest = out_dir.join(format!("OpenBLAS-{}", version));
+    let marker = out_dir.join(format!("OpenBLAS-{}.sha256", version));
+    if dest.exists() {
+        if fs::read_to_string(&marker).is_ok_and(|sha256| sha256.trim() == expected) {
+            return Ok(dest);
+        }
+        // Extracted from another tarball of the same version
+        fs::remove_dir_all(&dest)?;
+    }
+    fs::create_dir_all(out_dir)?;
+    let expected = expected.as_str();
+    let tarball = match source {
+        Source::Urls(urls) => {
+            let tarball = out_dir.join(format!("OpenBLAS-{}.tar.gz", version));
+            fetch_any(urls, &tarball, expected, network)?;
+            tarball
+        }
+        Source::Tarball(tarball) => {
+            verify(tarball, expected)?;
+            tarball.clone()
+        }
+        Source::Directory(_) => unreachable!(),
+    };
+
+    let tmp_dir = out_dir.join(format!(".OpenBLAS-{}.{}.tmp", version, std::process::id()));
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    let gz_stream = flate2::read::GzDecoder::new(io::BufReader::new(fs::File::open(&tarball)?));
+    let mut ar = tar::Archive::new(gz_stream);
+    ar.unpack(&tmp_dir)?;
+    if matches!(source, Source::Urls(_)) {
+        fs::remove_file(&tarball)?;
+    }
+    let extracted = tmp_dir.join(format!("OpenBLAS-{}", version));
+    if !extracted.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+        return Err(Error::SourceNotFound { path: extracted }.into());
+    }
+    // Another process may extract the same tarball concurrently
+    if let Err(e) = fs::rename(&extracted, &dest) {
+        if !dest.exists() {
+            fs::remove_dir_all(&tmp_dir)?;
+            return Err(e.into());
+        }
     }
+    fs::remove_dir_all(&tmp_dir)?;
+    fs::write(&marker, format!("{}\n", expected))?;
     Ok(dest)
 }
 
-fn get_agent() -> ureq::Agent {
+/// Store OpenBLAS source tarball of `version` into the content-addressed cache `cache_dir`,
+/// and returns it as [Source::Tarball] to be extracted by [download]
+///
+/// The tarball is saved as `{cache_dir}/{version}-{sha256}.tar.gz`,
+/// and is downloaded only when it does not exist yet.
+/// Download is serialized among processes by a lock file `.{version}-{sha256}.lock`,
+/// and the partially downloaded file `.{version}-{sha256}.tar.gz.part` is kept
+/// to be resumed by the next build if all URLs fail.
+/// Local sources, i.e. [Source::Tarball] and [Source::Directory], are returned as they are.
+pub fn cache_source(
+    source: &Source,
+    version: &str,
+    cache_dir: &Path,
+    sha256: Option<&str>,
+    network: &Network,
+) -> Result<Source> {
+    let urls = match source {
+        Source::Urls(urls) => urls,
+        _ => return Ok(source.clone()),
+    };
+    let expected = match sha256 {
+        Some(sha256) => sha256,
+        None => openblas_source_sha256(version)?,
+    };
+    if let Some(cached) = find_cached(cache_dir, version, expected) {
+        return Ok(Source::Tarball(cached));
+    }
+    fs::create_dir_all(cache_dir)?;
+    let name = format!("{}-{}", version, expected.to_ascii_lowercase());
+    // Wait for another process downloading the same tarball at most as long as its download takes
+    let attempts = network
+        .retries
+        .saturating_add(1)
+        .saturating_mul(urls.len() as u32);
+    let timeout = network.timeout.saturating_mul(attempts);
+    let _lock = BuildLock::acquire(cache_dir.join(format!(".{}.lock", name)), timeout)?;
+    if let Some(cached) = find_cached(cache_dir, version, expected) {
+        return Ok(Source::Tarball(cached));
+    }
+    let tmp = cache_dir.join(format!(".{}.tar.gz", name));
+    fetch_any(urls, &tmp, expected, network)?;
+    let cached = cache_dir.join(format!("{}-{}.tar.gz", version, sha256_file(&tmp)?));
+    fs::rename(&tmp, &cached)?;
+    Ok(Source::Tarball(cached))
+}
+
+/// Find the cached tarball of `version` whose name has the checksum `expected`
+///
+/// A tarball cached with another checksum, e.g. downloaded from another URL, is never reused.
+fn find_cached(cache_dir: &Path, version: &str, expected: &str) -> Option<PathBuf> {
+    let cached = cache_dir.join(format!(
+        "{}-{}.tar.gz",
+        version,
+        expected.to_ascii_lowercase()
+    ));
+    if cached.exists() {
+        Some(cached)
+    } else {
+        None
+    }
+}
+
+/// Download a tarball into `tarball` from the first URL succeeded in downloading and verification
+fn fetch_any(urls: &[String], tarball: &Path, expected: &str, network: &Network) -> Result<()> {
+    let mut last_error = None;
+    for url in urls {
+        match fetch(url, tarball, network).and_then(|_| verify(tarball, expected)) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                println!(
+                    "cargo:warning=OpenBLAS: Failed to download from {}: {}",
+                    url, e
+                );
+                let _ = fs::remove_file(tarball);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        Error::SourceNotFound {
+            path: tarball.to_owned(),
+        }
+        .into()
+    }))
+}
+
+/// Download a file from `url` to `dest`
+///
+/// HTTP(S) download is saved into `{dest}.part` first, and renamed to `dest` when completed.
+/// The part is kept when the download fails to be resumed later.
+fn fetch(url: &str, dest: &Path, network: &Network) -> Result<()> {
+    if let Some(path) = url.strip_prefix("file://") {
+        fs::copy(path, dest)?;
+        return Ok(());
+    }
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(Error::UnsupportedUrl {
+            url: url.to_string(),
+        }
+        .into());
+    }
+    if is_offline() {
+        return Err(Error::Offline {
+            url: url.to_string(),
+        }
+        .into());
+    }
+    let part = dest.with_extension("gz.part");
+    let agent = get_agent(network);
+    let mut backoff = network.backoff;
+    let mut attempt = 0;
+    loop {
+        match fetch_part(&agent, url, &part) {
+            Ok(_) => break,
+            Err(e) if attempt < network.retries && is_retryable(&e) => {
+                attempt += 1;
+                println!(
+                    "cargo:warning=OpenBLAS: Retry downloading {} in {:?} ({}/{}): {}",
+                    url, backoff, attempt, network.retries, e
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    fs::rename(&part, dest)?;
+    Ok(())
+}
+
+/// Download `url` into `part`, resuming from its current length using HTTP range request
+fn fetch_part(agent: &ureq::Agent, url: &str, part: &Path) -> Result<(), ureq::Error> {
+    let offset = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let mut request = agent.get(url);
+    if offset > 0 {
+        request = request.header("Range", &format!("bytes={}-", offset));
+    }
+    let response = match request.call() {
+        // The part already has whole content
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => return Ok(()),
+        response => response?,
+    };
+    let mut f = if response.status().as_u16() == 206 {
+        fs::OpenOptions::new().append(true).open(part)?
+    } else {
+        // Server does not support range request, and sends whole content
+        fs::File::create(part)?
+    };
+    io::copy(&mut response.into_body().into_reader(), &mut f)?;
+    Ok(())
+}
+
+/// Errors which may be resolved by retry, e.g. timeout or server error
+fn is_retryable(e: &ureq::Error) -> bool {
+    match e {
+        ureq::Error::StatusCode(status) => *status == 408 || *status == 429 || *status >= 500,
+        ureq::Error::BadUri(_) | ureq::Error::InvalidProxyUrl | ureq::Error::Tls(_) => false,
+        _ => true,
+    }
+}
+
+/// Check SHA-256 of `tarball`
+fn verify(tarball: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(tarball)?;
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(Error::ChecksumMismatch {
+            path: tarball.to_owned(),
+            expected: expected.to_string(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// SHA-256 of file as lower-case hex string
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn get_agent(network: &Network) -> ureq::Agent {
     Config::builder()
+        .timeout_connect(Some(network.connect_timeout))
+        .timeout_global(Some(network.timeout))
         .tls_config(
             TlsConfig::builder()
                 .provider(TlsProvider::NativeTls)
@@ -40,3 +375,63 @@ fn get_agent() -> ureq::Agent {
         .build()
         .new_agent()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openblas_versions() {
+        assert!(OPENBLAS_VERSIONS
+            .iter()
+            .any(|(version, _)| *version == OPENBLAS_VERSION));
+        for (i, (version, sha256)) in OPENBLAS_VERSIONS.iter().enumerate() {
+            assert!(
+                OPENBLAS_VERSIONS[..i].iter().all(|(v, _)| v != version),
+                "{} is listed twice",
+                version
+            );
+            // Pinned checksum must be exactly what `sha256sum` prints
+            let sha256 = sha256.unwrap_or_else(|| panic!("No SHA-256 is pinned for {}", version));
+            assert!(
+                sha256.len() == 64
+                    && sha256
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)),
+                "Invalid SHA-256 for {}: {}",
+                version,
+                sha256
+            );
+        }
+    }
+
+    #[test]
+    fn download_offline() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build/download_offline");
+        std::env::set_var("CARGO_NET_OFFLINE", "true");
+        let err = download(
+            &Source::default(),
+            OPENBLAS_VERSION,
+            &root,
+            Some("0000"),
+            &Network::default(),
+        )
+        .unwrap_err();
+        std::env::remove_var("CARGO_NET_OFFLINE");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Offline { .. })
+        ));
+    }
+
+    #[test]
+    fn sha256_of_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build/sha256_of_file");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -24,12 +24,57 @@ pub enum Error {
     #[error("Target {} is unsupported", target)]
     UnsupportedTarget { target: String },
 
+    #[error("At least one precision must be built")]
+    NoPrecision,
+
+    #[error("Routines of requested precisions are not built: {}", precisions)]
+    MissingPrecisions { precisions: String },
+
     #[error(
         "Cross compile information is missing and cannot be inferred, {}",
         info
     )]
     MissingCrossCompileInfo { info: String },
 
+    #[error(
+        "Checksum of {} does not match: expected {}, actual {}",
+        path.display(),
+        expected,
+        actual
+    )]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    #[error(
+        "OpenBLAS {} is unknown. Its SHA-256 must be given explicitly.",
+        version
+    )]
+    UnknownVersion { version: String },
+
+    #[error(
+        "No SHA-256 is pinned for OpenBLAS {}. Set OPENBLAS_SOURCE_SHA256 to verify the source.",
+        version
+    )]
+    ChecksumNotPinned { version: String },
+
+    #[error(
+        "Cannot download {} since cargo is offline. Use local OpenBLAS source instead.",
+        url
+    )]
+    Offline { url: String },
+
+    #[error("Unsupported URL: {}", url)]
+    UnsupportedUrl { url: String },
+
+    #[error("OpenBLAS source does not exist at {}", path.display())]
+    SourceNotFound { path: PathBuf },
+
+    #[error("Timed out waiting for lock {}", path.display())]
+    LockTimeout { path: PathBuf },
+
     #[error("Other IO errors: {0:?}")]
     IOError(#[from] io::Error),
 }
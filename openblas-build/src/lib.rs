@@ -13,9 +13,16 @@
 //! [OpenBLAS]: https://github.com/OpenMathLib/OpenBLAS
 
 mod build;
+mod cache;
 mod check;
 mod download;
 pub mod error;
+mod lock;
+mod package;
+mod target;
 pub use build::*;
+pub use cache::*;
 pub use check::*;
 pub use download::*;
+pub use lock::*;
+pub use package::*;
@@ -0,0 +1,181 @@
+use crate::error::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Interval to check if the lock is released
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Advisory lock of a build directory shared among processes
+///
+/// The lock is held on an open file by the OS, i.e. `flock` on Unix and an exclusive
+/// share mode on Windows, and is released when [BuildLock] is dropped
+/// or its process exits, even if it crashes.
+/// The lock file itself is kept, and contains the process ID of the last owner for inspection.
+#[derive(Debug)]
+pub struct BuildLock {
+    path: PathBuf,
+    _file: fs::File,
+}
+
+impl BuildLock {
+    /// Acquire the lock file `path`, waiting for other processes at most `timeout`
+    pub fn acquire<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let start = SystemTime::now();
+        let mut waiting = false;
+        loop {
+            if let Some(lock) = Self::try_acquire(path)? {
+                return Ok(lock);
+            }
+            if start.elapsed().unwrap_or_default() > timeout {
+                return Err(Error::LockTimeout {
+                    path: path.to_owned(),
+                });
+            }
+            if !waiting {
+                println!(
+                    "cargo:warning=OpenBLAS: Waiting for another build holding {}",
+                    path.display()
+                );
+                waiting = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Acquire the lock file `path` if no other process holds it, without waiting
+    pub fn try_acquire<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Error> {
+        let path = path.as_ref();
+        let mut file = match open_locked(path)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Some(BuildLock {
+            path: path.to_owned(),
+            _file: file,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<Option<fs::File>> {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+    loop {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(e),
+            };
+        }
+        // The file may be removed or replaced by another process before locked,
+        // e.g. with its build directory by `openblas-build remove`
+        let locked = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(Some(file))
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<Option<fs::File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    // Other processes cannot open the file for writing until it is closed
+    match fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_DELETE)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn open_locked(path: &Path) -> io::Result<Option<fs::File>> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_build");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let path = lock_path("lock_timeout.lock");
+        let lock = BuildLock::acquire(&path, Duration::from_secs(1)).unwrap();
+        assert!(path.exists());
+        let err = BuildLock::acquire(&path, Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(err, Error::LockTimeout { .. }));
+        assert!(BuildLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        BuildLock::acquire(&path, Duration::from_millis(100)).unwrap();
+    }
+
+    #[test]
+    fn lock_left() {
+        // Lock file left by a crashed process, which no longer holds the lock
+        let path = lock_path("lock_left.lock");
+        fs::write(&path, "4294967295\n").unwrap();
+        let lock = BuildLock::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(lock.path()).unwrap().trim(),
+            std::process::id().to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lock_removed() {
+        // A lock file removed while another process waits for it is not shared
+        let path = lock_path("lock_removed.lock");
+        let lock = BuildLock::try_acquire(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        let other = BuildLock::try_acquire(&path).unwrap().unwrap();
+        assert!(BuildLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(BuildLock::try_acquire(&path).unwrap().is_none());
+        drop(other);
+    }
+}
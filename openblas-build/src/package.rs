@@ -0,0 +1,182 @@
+//! pkg-config and CMake package files for installed OpenBLAS
+
+use crate::check::MakeConf;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Search paths and libraries required to link static OpenBLAS,
+/// i.e. `FEXTRALIB` and `CEXTRALIB` in `Makefile.conf`, and the OpenMP runtime if `openmp`
+fn private_libs(make_conf: &MakeConf, openmp: bool) -> (Vec<&PathBuf>, Vec<&str>) {
+    let mut search_paths = Vec::new();
+    let mut libs = Vec::new();
+    for flags in [&make_conf.f_extra_libs, &make_conf.c_extra_libs] {
+        for path in &flags.search_paths {
+            if !search_paths.contains(&path) {
+                search_paths.push(path);
+            }
+        }
+        for lib in &flags.libs {
+            if !libs.contains(&lib.as_str()) {
+                libs.push(lib.as_str());
+            }
+        }
+    }
+    if openmp && !libs.contains(&make_conf.openmp_lib()) {
+        libs.push(make_conf.openmp_lib());
+    }
+    (search_paths, libs)
+}
+
+/// Installed library in `prefix/lib`, shared one is preferred
+fn library_file(prefix: &Path) -> PathBuf {
+    let lib_dir = prefix.join("lib");
+    ["libopenblas.so", "libopenblas.dylib"]
+        .iter()
+        .map(|name| lib_dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| lib_dir.join("libopenblas.a"))
+}
+
+/// Write `openblas.pc` into `prefix/lib/pkgconfig` and returns its path
+///
+/// `openmp` is whether OpenBLAS is built with OpenMP, which requires its runtime.
+pub fn write_pkg_config(
+    prefix: &Path,
+    version: &str,
+    make_conf: &MakeConf,
+    openmp: bool,
+) -> io::Result<PathBuf> {
+    let (search_paths, libs) = private_libs(make_conf, openmp);
+    let libs_private: Vec<String> = search_paths
+        .iter()
+        .map(|path| format!("-L{}", path.display()))
+        .chain(libs.iter().map(|lib| format!("-l{}", lib)))
+        .collect();
+    let content = format!(
+        "prefix={prefix}
+libdir=${{prefix}}/lib
+includedir=${{prefix}}/include
+
+Name: openblas
+Description: OpenBLAS is an optimized BLAS library based on GotoBLAS2 1.13 BSD version
+URL: https://github.com/OpenMathLib/OpenBLAS
+Version: {version}
+Libs: -L${{libdir}} -lopenblas
+Libs.private: {libs_private}
+Cflags: -I${{includedir}}
+",
+        prefix = prefix.display(),
+        version = version,
+        libs_private = libs_private.join(" "),
+    );
+    let dir = prefix.join("lib/pkgconfig");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("openblas.pc");
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Write `OpenBLASConfig.cmake` and `OpenBLASConfigVersion.cmake` into `prefix/lib/cmake/openblas`,
+/// and returns the directory
+///
+/// They define `OpenBLAS::OpenBLAS` imported target
+/// and `OpenBLAS_INCLUDE_DIRS`, `OpenBLAS_LIBRARIES` variables for `find_package(OpenBLAS)`.
+/// `openmp` is the same as [write_pkg_config].
+pub fn write_cmake_config(
+    prefix: &Path,
+    version: &str,
+    make_conf: &MakeConf,
+    openmp: bool,
+) -> io::Result<PathBuf> {
+    let (search_paths, libs) = private_libs(make_conf, openmp);
+    let cmake_list = |items: Vec<String>| items.join(";");
+    let library = library_file(prefix);
+    let link_dirs = cmake_list(
+        search_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+    );
+    let link_libs = cmake_list(libs.iter().map(|lib| lib.to_string()).collect());
+    let config = format!(
+        r#"# Generated by openblas-build
+set(OpenBLAS_VERSION "{version}")
+set(OpenBLAS_INCLUDE_DIRS "{include_dir}")
+set(OpenBLAS_LIBRARIES "{library};{link_libs}")
+
+if(NOT TARGET OpenBLAS::OpenBLAS)
+  add_library(OpenBLAS::OpenBLAS UNKNOWN IMPORTED)
+  set_target_properties(OpenBLAS::OpenBLAS PROPERTIES
+    IMPORTED_LOCATION "{library}"
+    INTERFACE_INCLUDE_DIRECTORIES "{include_dir}"
+    INTERFACE_LINK_DIRECTORIES "{link_dirs}"
+    INTERFACE_LINK_LIBRARIES "{link_libs}"
+  )
+endif()
+"#,
+        version = version,
+        include_dir = prefix.join("include").display(),
+        library = library.display(),
+        link_dirs = link_dirs,
+        link_libs = link_libs,
+    );
+    let config_version = format!(
+        r#"# Generated by openblas-build
+set(PACKAGE_VERSION "{version}")
+if(PACKAGE_FIND_VERSION VERSION_GREATER PACKAGE_VERSION)
+  set(PACKAGE_VERSION_COMPATIBLE FALSE)
+else()
+  set(PACKAGE_VERSION_COMPATIBLE TRUE)
+  if(PACKAGE_FIND_VERSION STREQUAL PACKAGE_VERSION)
+    set(PACKAGE_VERSION_EXACT TRUE)
+  endif()
+endif()
+"#,
+        version = version
+    );
+    let dir = prefix.join("lib/cmake/openblas");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("OpenBLASConfig.cmake"), config)?;
+    fs::write(dir.join("OpenBLASConfigVersion.cmake"), config_version)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_config() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let prefix = root.join("test_build/package_config");
+        let _ = fs::remove_dir_all(&prefix);
+        let make_conf = MakeConf::new(root.join("Makefile.conf")).unwrap();
+
+        let pc =
+            fs::read_to_string(write_pkg_config(&prefix, "0.3.31", &make_conf, false).unwrap())
+                .unwrap();
+        assert!(pc.contains(&format!("prefix={}\n", prefix.display())));
+        assert!(pc.contains("Version: 0.3.31\n"));
+        assert!(pc.contains("Libs: -L${libdir} -lopenblas\n"));
+        let libs_private = pc
+            .lines()
+            .find_map(|line| line.strip_prefix("Libs.private: "))
+            .unwrap();
+        assert!(libs_private.ends_with("-lc -lgfortran -lm -lquadmath"));
+
+        let dir = write_cmake_config(&prefix, "0.3.31", &make_conf, false).unwrap();
+        let config = fs::read_to_string(dir.join("OpenBLASConfig.cmake")).unwrap();
+        assert!(config.contains("INTERFACE_LINK_LIBRARIES \"c;gfortran;m;quadmath\""));
+        assert!(dir.join("OpenBLASConfigVersion.cmake").exists());
+
+        // OpenMP runtime is required for OpenMP builds
+        let pc = fs::read_to_string(write_pkg_config(&prefix, "0.3.31", &make_conf, true).unwrap())
+            .unwrap();
+        assert!(pc.contains("-lquadmath -lgomp\n"));
+        let dir = write_cmake_config(&prefix, "0.3.31", &make_conf, true).unwrap();
+        let config = fs::read_to_string(dir.join("OpenBLASConfig.cmake")).unwrap();
+        assert!(config.contains("INTERFACE_LINK_LIBRARIES \"c;gfortran;m;quadmath;gomp\""));
+    }
+}
@@ -0,0 +1,544 @@
+//! Mapping from Rust target triple, and `-C target-cpu` and target features of rustc,
+//! to OpenBLAS [Target]
+
+use crate::{
+    build::{Configure, Target},
+    error::Error,
+};
+use std::env;
+
+/// Rules to select OpenBLAS target for a Rust target triple, searched in order
+///
+/// Each rule is `(arch, vendor, env, target)`:
+///
+/// - `arch` is compared with the first component of the triple, e.g. `armv7`,
+/// - `vendor` with the second component, e.g. `apple`, or matches any vendor if empty,
+/// - `env` is a suffix of the last component, e.g. `eabihf` for `gnueabihf` and `musleabihf`,
+///   or matches anything if empty,
+/// - `target` is the most conservative OpenBLAS target the triple guarantees,
+///   or `None` if OpenBLAS does not support the triple.
+const RULES: &[(&str, &str, &str, Option<Target>)] = &[
+    // X86_64, `x86_64h` is Haswell or later
+    ("x86_64", "", "x32", None),
+    ("x86_64", "", "", Some(Target::SSE_GENERIC)),
+    ("x86_64h", "", "", Some(Target::HASWELL)),
+    // X86, `i686` requires SSE2, but `i586` does not
+    ("i686", "", "", Some(Target::SSE_GENERIC)),
+    ("i586", "", "", Some(Target::GENERIC)),
+    // ARM64, Apple silicon is `VORTEX` or later
+    ("aarch64", "apple", "", Some(Target::VORTEX)),
+    ("arm64e", "apple", "", Some(Target::VORTEX)),
+    ("aarch64", "", "", Some(Target::ARMV8)),
+    ("aarch64_be", "", "", Some(Target::ARMV8)),
+    ("arm64ec", "", "", Some(Target::ARMV8)),
+    ("arm64_32", "", "", None),
+    // ARM, kernels other than `ARMV5` require VFP of hard-float ABI,
+    // or NEON of softfp ABI on Android, which requires `ARM_SOFTFP_ABI=1`
+    ("arm", "", "eabihf", Some(Target::ARMV6)),
+    ("arm", "", "", Some(Target::ARMV5)),
+    ("armv5te", "", "", Some(Target::ARMV5)),
+    ("armv6", "", "eabihf", Some(Target::ARMV6)),
+    ("armv6", "", "", Some(Target::ARMV5)),
+    ("armv7", "", "eabihf", Some(Target::ARMV7)),
+    ("armv7", "", "androideabi", Some(Target::ARMV7)),
+    ("armv7", "", "", Some(Target::ARMV5)),
+    ("armv7s", "apple", "", Some(Target::ARMV7)),
+    ("thumbv7neon", "", "eabihf", Some(Target::ARMV7)),
+    ("thumbv7neon", "", "androideabi", Some(Target::ARMV7)),
+    ("thumbv7neon", "", "", Some(Target::ARMV5)),
+    // Power, 64-bit little endian is POWER8 or later
+    ("powerpc64le", "", "", Some(Target::POWER8)),
+    ("powerpc64", "ibm", "", Some(Target::POWER7)),
+    ("powerpc64", "", "", Some(Target::PPC970)),
+    ("powerpc", "", "", Some(Target::PPC440)),
+    // MIPS
+    ("mips", "", "", Some(Target::MIPS24K)),
+    ("mipsel", "", "", Some(Target::MIPS24K)),
+    // MIPS64, release 6 is not compatible with the former releases
+    ("mips64", "", "", Some(Target::MIPS64_GENERIC)),
+    ("mips64el", "", "", Some(Target::MIPS64_GENERIC)),
+    ("mipsisa64r6", "", "", Some(Target::I6400)),
+    ("mipsisa64r6el", "", "", Some(Target::I6400)),
+    // IA64
+    ("ia64", "", "", Some(Target::ITANIUM2)),
+    // Sparc
+    ("sparc", "", "", Some(Target::SPARCV7)),
+    ("sparc64", "", "", Some(Target::SPARC)),
+    ("sparcv9", "", "", Some(Target::SPARC)),
+    // System Z
+    ("s390x", "", "", Some(Target::ZARCH_GENERIC)),
+    // RISC-V 64, RVA23 profile includes the vector extension
+    ("riscv64gc", "", "", Some(Target::RISCV64_GENERIC)),
+    ("riscv64", "", "", Some(Target::RISCV64_GENERIC)),
+    ("riscv64a23", "", "", Some(Target::RISCV64_ZVL128B)),
+    // LoongArch64
+    ("loongarch64", "", "", Some(Target::LOONGSONGENERIC)),
+    // Elbrus E2000
+    ("e2k64", "", "", Some(Target::E2K)),
+    // Alpha
+    ("alpha", "", "", Some(Target::EV4)),
+    // CSKY
+    ("csky", "", "", Some(Target::CK860FV)),
+];
+
+/// CPUs of `-C target-cpu`, i.e. LLVM processor names, and corresponding OpenBLAS targets
+const TARGET_CPUS: &[(&str, Target)] = &[
+    // X86/X86_64 Intel
+    ("x86-64", Target::SSE_GENERIC),
+    ("x86-64-v2", Target::NEHALEM),
+    ("x86-64-v3", Target::HASWELL),
+    ("x86-64-v4", Target::SKYLAKEX),
+    ("prescott", Target::PRESCOTT),
+    ("nocona", Target::PRESCOTT),
+    ("core2", Target::CORE2),
+    ("penryn", Target::PENRYN),
+    ("bonnell", Target::ATOM),
+    ("atom", Target::ATOM),
+    ("silvermont", Target::NEHALEM),
+    ("goldmont", Target::NEHALEM),
+    ("nehalem", Target::NEHALEM),
+    ("westmere", Target::NEHALEM),
+    ("sandybridge", Target::SANDYBRIDGE),
+    ("ivybridge", Target::SANDYBRIDGE),
+    ("haswell", Target::HASWELL),
+    ("broadwell", Target::HASWELL),
+    ("skylake", Target::HASWELL),
+    ("alderlake", Target::HASWELL),
+    ("raptorlake", Target::HASWELL),
+    ("meteorlake", Target::HASWELL),
+    ("skylake-avx512", Target::SKYLAKEX),
+    ("cascadelake", Target::SKYLAKEX),
+    ("icelake-client", Target::SKYLAKEX),
+    ("icelake-server", Target::SKYLAKEX),
+    ("tigerlake", Target::SKYLAKEX),
+    ("rocketlake", Target::SKYLAKEX),
+    ("cooperlake", Target::COOPERLAKE),
+    ("sapphirerapids", Target::SAPPHIRERAPIDS),
+    ("emeraldrapids", Target::SAPPHIRERAPIDS),
+    ("graniterapids", Target::SAPPHIRERAPIDS),
+    // X86/X86_64 AMD
+    ("k8", Target::OPTERON),
+    ("opteron", Target::OPTERON),
+    ("athlon64", Target::OPTERON),
+    ("k8-sse3", Target::OPTERON_SSE3),
+    ("opteron-sse3", Target::OPTERON_SSE3),
+    ("amdfam10", Target::BARCELONA),
+    ("barcelona", Target::BARCELONA),
+    ("btver1", Target::BOBCAT),
+    ("btver2", Target::BOBCAT),
+    ("bdver1", Target::BULLDOZER),
+    ("bdver2", Target::PILEDRIVER),
+    ("bdver3", Target::STEAMROLLER),
+    ("bdver4", Target::EXCAVATOR),
+    ("znver1", Target::ZEN),
+    ("znver2", Target::ZEN),
+    ("znver3", Target::ZEN),
+    // OpenBLAS uses the kernels of Cooper Lake for Zen 4 and later
+    ("znver4", Target::COOPERLAKE),
+    ("znver5", Target::COOPERLAKE),
+    // ARM64, `generic` is not listed since it is the name for every architecture
+    ("cortex-a53", Target::CORTEXA53),
+    ("cortex-a55", Target::CORTEXA55),
+    ("cortex-a57", Target::CORTEXA57),
+    ("cortex-a72", Target::CORTEXA72),
+    ("cortex-a73", Target::CORTEXA73),
+    ("cortex-a76", Target::CORTEXA76),
+    ("cortex-a510", Target::CORTEXA510),
+    ("cortex-a710", Target::CORTEXA710),
+    ("cortex-x1", Target::CORTEXX1),
+    ("cortex-x2", Target::CORTEXX2),
+    ("neoverse-n1", Target::NEOVERSEN1),
+    ("neoverse-v1", Target::NEOVERSEV1),
+    ("neoverse-n2", Target::NEOVERSEN2),
+    ("neoverse-v2", Target::NEOVERSEV2),
+    ("a64fx", Target::A64FX),
+    ("falkor", Target::FALKOR),
+    ("thunderx", Target::THUNDERX),
+    ("thunderx2t99", Target::THUNDERX2T99),
+    ("thunderx3t110", Target::THUNDERX3T110),
+    ("tsv110", Target::TSV110),
+    ("apple-m1", Target::VORTEX),
+    ("apple-m2", Target::VORTEX),
+    ("apple-m3", Target::VORTEX),
+    ("apple-m4", Target::VORTEXM4),
+    // ARM
+    ("cortex-a9", Target::CORTEXA9),
+    ("cortex-a15", Target::CORTEXA15),
+    // Power
+    ("pwr4", Target::POWER4),
+    ("pwr5", Target::POWER5),
+    ("pwr6", Target::POWER6),
+    ("pwr7", Target::POWER7),
+    ("pwr8", Target::POWER8),
+    ("pwr9", Target::POWER9),
+    ("pwr10", Target::POWER10),
+    ("pwr11", Target::POWER11),
+    ("970", Target::PPC970),
+    ("g5", Target::PPC970),
+    ("g4", Target::PPCG4),
+    ("440", Target::PPC440),
+    // System Z
+    ("z13", Target::Z13),
+    ("arch11", Target::Z13),
+    ("z14", Target::Z14),
+    ("arch12", Target::Z14),
+    ("z15", Target::Z14),
+    ("arch13", Target::Z14),
+    ("z16", Target::Z14),
+    ("arch14", Target::Z14),
+    // RISC-V 64
+    ("sifive-x280", Target::x280),
+    // LoongArch64
+    ("la264", Target::LA264),
+    ("la464", Target::LA464),
+];
+
+/// Target features of rustc required for OpenBLAS targets, searched in order
+///
+/// Each rule is `(arch, features, target)`,
+/// where `arch` is compared with `CARGO_CFG_TARGET_ARCH`.
+const TARGET_FEATURES: &[(&str, &[&str], Target)] = &[
+    (
+        "x86_64",
+        &["avx512f", "avx512vl", "avx512bw", "avx512dq"],
+        Target::SKYLAKEX,
+    ),
+    ("x86_64", &["avx2", "fma"], Target::HASWELL),
+    ("x86_64", &["avx"], Target::SANDYBRIDGE),
+    ("x86_64", &["sse4.2"], Target::NEHALEM),
+    ("x86_64", &["ssse3"], Target::CORE2),
+    ("x86_64", &["sse3"], Target::PRESCOTT),
+    ("x86", &["avx2", "fma"], Target::HASWELL),
+    ("x86", &["avx"], Target::SANDYBRIDGE),
+    ("x86", &["sse4.2"], Target::NEHALEM),
+    ("x86", &["ssse3"], Target::CORE2),
+    ("x86", &["sse3"], Target::PRESCOTT),
+    ("aarch64", &["sve"], Target::ARMV8SVE),
+    ("riscv64", &["v"], Target::RISCV64_ZVL128B),
+];
+
+/// `-C target-cpu` in `CARGO_ENCODED_RUSTFLAGS`, where the last one is used as rustc does
+fn target_cpu(encoded_rustflags: &str) -> Option<&str> {
+    let mut cpu = None;
+    let mut flags = encoded_rustflags.split('\x1f');
+    while let Some(flag) = flags.next() {
+        let codegen = match flag {
+            "-C" | "--codegen" => flags.next().unwrap_or_default(),
+            _ => flag
+                .strip_prefix("-C")
+                .or_else(|| flag.strip_prefix("--codegen="))
+                .unwrap_or_default(),
+        };
+        if let Some(value) = codegen.strip_prefix("target-cpu=") {
+            cpu = Some(value);
+        }
+    }
+    cpu
+}
+
+impl Target {
+    /// OpenBLAS target for `-C target-cpu` of rustc, e.g. `ZEN` for `znver3`
+    pub fn from_target_cpu(cpu: &str) -> Option<Self> {
+        TARGET_CPUS
+            .iter()
+            .find(|(name, _)| *name == cpu)
+            .map(|(_, target)| *target)
+    }
+
+    /// OpenBLAS target for target features enabled in rustc, i.e. `CARGO_CFG_TARGET_FEATURE`,
+    /// e.g. `HASWELL` for `avx2` and `fma` on `x86_64`
+    pub fn from_target_features(arch: &str, features: &[&str]) -> Option<Self> {
+        TARGET_FEATURES
+            .iter()
+            .find(|(rule_arch, required, _)| {
+                *rule_arch == arch && required.iter().all(|feature| features.contains(feature))
+            })
+            .map(|(_, _, target)| *target)
+    }
+
+    /// OpenBLAS target for a Rust target triple, e.g. `ARMV7` for `armv7-unknown-linux-gnueabihf`,
+    /// used when cross compiling without `TARGET`
+    ///
+    /// Since the target CPU is unknown, the generic one of the architecture is selected
+    /// unless the triple itself specifies the CPU, e.g. `x86_64h-apple-darwin`.
+    /// Returns `None` if OpenBLAS does not support the triple.
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let components: Vec<&str> = triple.split('-').collect();
+        let arch = components[0];
+        let vendor = components.get(1).copied().unwrap_or_default();
+        let env = components.last().copied().unwrap_or_default();
+        RULES
+            .iter()
+            .find(|(rule_arch, rule_vendor, rule_env, _)| {
+                *rule_arch == arch
+                    && (rule_vendor.is_empty() || *rule_vendor == vendor)
+                    && env.ends_with(rule_env)
+            })
+            .and_then(|(_, _, _, target)| *target)
+    }
+
+    /// OpenBLAS target when building for the triple `target` on `host` without `TARGET`,
+    /// or `None` if OpenBLAS can detect the CPU of the build host
+    ///
+    /// The build host is detected when it is the same triple, or a glibc host builds
+    /// for musl of the same architecture and ABI, e.g. `x86_64-unknown-linux-musl`
+    /// on `x86_64-unknown-linux-gnu`. Other triples of the same architecture, e.g.
+    /// `x86_64-unknown-linux-gnux32` or `arm-unknown-linux-gnueabi` on
+    /// `arm-unknown-linux-gnueabihf`, use [Target::from_triple].
+    pub(crate) fn for_cross_compile(target: &str, host: &str) -> Result<Option<Self>, Error> {
+        let musl_of_host = match (target.rsplit_once("-musl"), host.rsplit_once("-gnu")) {
+            (Some(target), Some(host)) => target == host,
+            _ => false,
+        };
+        if target == host || musl_of_host {
+            return Ok(None);
+        }
+        match Target::from_triple(target) {
+            Some(target) => Ok(Some(target)),
+            None => Err(Error::MissingCrossCompileInfo {
+                info: "TARGET".to_string(),
+            }),
+        }
+    }
+}
+
+impl Configure {
+    /// Select [Configure::target], [Configure::no_avx512] and [Configure::no_avx2]
+    /// from `-C target-cpu` and target features of rustc instead of the CPU of the build host
+    ///
+    /// Options already set are kept, and the decision is reported as a cargo warning.
+    pub fn target_from_rustc(&mut self) {
+        let triple = env::var("TARGET").unwrap_or_default();
+        let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+        let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+        let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+        let features: Vec<&str> = features.split(',').collect();
+        let decision = self.apply_rustc_target(&triple, &arch, target_cpu(&rustflags), &features);
+        println!("cargo:warning=OpenBLAS: {}", decision);
+    }
+
+    fn apply_rustc_target(
+        &mut self,
+        triple: &str,
+        arch: &str,
+        cpu: Option<&str>,
+        features: &[&str],
+    ) -> String {
+        if arch == "x86_64" || arch == "x86" {
+            self.no_avx512 = self.no_avx512.or(Some(!features.contains(&"avx512f")));
+            self.no_avx2 = self.no_avx2.or(Some(!features.contains(&"avx2")));
+        }
+        let mut decision = if let Some(target) = self.target {
+            format!("TARGET={:?} is specified explicitly", target)
+        } else if cpu == Some("native") {
+            "target-cpu=native, the CPU of the build host is detected".to_string()
+        } else if let Some((cpu, target)) =
+            cpu.and_then(|cpu| Some((cpu, Target::from_target_cpu(cpu)?)))
+        {
+            self.target = Some(target);
+            format!("TARGET={:?} for target-cpu={}", target, cpu)
+        } else if let Some(target) = Target::from_target_features(arch, features) {
+            self.target = Some(target);
+            format!("TARGET={:?} for target features", target)
+        } else if let Some(target) = Target::from_triple(triple) {
+            self.target = Some(target);
+            format!("TARGET={:?} for {}", target, triple)
+        } else {
+            format!(
+                "No TARGET for {}, the CPU of the build host is detected",
+                triple
+            )
+        };
+        if let (Some(no_avx512), Some(no_avx2)) = (self.no_avx512, self.no_avx2) {
+            decision += &format!(", NO_AVX512={}, NO_AVX2={}", no_avx512 as u8, no_avx2 as u8);
+        }
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linux_triples() {
+        let triples = [
+            // Tier 1
+            ("aarch64-unknown-linux-gnu", Some(Target::ARMV8)),
+            ("i686-unknown-linux-gnu", Some(Target::SSE_GENERIC)),
+            ("x86_64-unknown-linux-gnu", Some(Target::SSE_GENERIC)),
+            // Tier 2 with host tools
+            ("aarch64-unknown-linux-musl", Some(Target::ARMV8)),
+            ("aarch64-unknown-linux-ohos", Some(Target::ARMV8)),
+            ("arm-unknown-linux-gnueabi", Some(Target::ARMV5)),
+            ("arm-unknown-linux-gnueabihf", Some(Target::ARMV6)),
+            ("armv7-unknown-linux-gnueabihf", Some(Target::ARMV7)),
+            (
+                "loongarch64-unknown-linux-gnu",
+                Some(Target::LOONGSONGENERIC),
+            ),
+            (
+                "loongarch64-unknown-linux-musl",
+                Some(Target::LOONGSONGENERIC),
+            ),
+            ("powerpc-unknown-linux-gnu", Some(Target::PPC440)),
+            ("powerpc64-unknown-linux-gnu", Some(Target::PPC970)),
+            ("powerpc64le-unknown-linux-gnu", Some(Target::POWER8)),
+            ("powerpc64le-unknown-linux-musl", Some(Target::POWER8)),
+            ("riscv64gc-unknown-linux-gnu", Some(Target::RISCV64_GENERIC)),
+            (
+                "riscv64gc-unknown-linux-musl",
+                Some(Target::RISCV64_GENERIC),
+            ),
+            ("s390x-unknown-linux-gnu", Some(Target::ZARCH_GENERIC)),
+            ("x86_64-unknown-linux-musl", Some(Target::SSE_GENERIC)),
+            ("x86_64-unknown-linux-ohos", Some(Target::SSE_GENERIC)),
+            // Tier 2 without host tools
+            ("arm-unknown-linux-musleabi", Some(Target::ARMV5)),
+            ("arm-unknown-linux-musleabihf", Some(Target::ARMV6)),
+            ("armv5te-unknown-linux-gnueabi", Some(Target::ARMV5)),
+            ("armv5te-unknown-linux-musleabi", Some(Target::ARMV5)),
+            ("armv7-unknown-linux-gnueabi", Some(Target::ARMV5)),
+            ("armv7-unknown-linux-musleabi", Some(Target::ARMV5)),
+            ("armv7-unknown-linux-musleabihf", Some(Target::ARMV7)),
+            ("armv7-unknown-linux-ohos", Some(Target::ARMV5)),
+            ("i586-unknown-linux-gnu", Some(Target::GENERIC)),
+            ("i586-unknown-linux-musl", Some(Target::GENERIC)),
+            ("i686-unknown-linux-musl", Some(Target::SSE_GENERIC)),
+            ("sparc64-unknown-linux-gnu", Some(Target::SPARC)),
+            ("thumbv7neon-unknown-linux-gnueabihf", Some(Target::ARMV7)),
+            ("x86_64-unknown-linux-gnux32", None),
+        ];
+        for (triple, target) in triples {
+            assert_eq!(Target::from_triple(triple), target, "{}", triple);
+        }
+    }
+
+    #[test]
+    fn cross_compile() {
+        let host = "x86_64-unknown-linux-gnu";
+        assert_eq!(Target::for_cross_compile(host, host).unwrap(), None);
+        assert_eq!(
+            Target::for_cross_compile("x86_64-unknown-linux-musl", host).unwrap(),
+            None
+        );
+        assert_eq!(
+            Target::for_cross_compile("i686-unknown-linux-gnu", host).unwrap(),
+            Some(Target::SSE_GENERIC)
+        );
+        assert!(matches!(
+            Target::for_cross_compile("x86_64-unknown-linux-gnux32", host),
+            Err(Error::MissingCrossCompileInfo { .. })
+        ));
+
+        let host = "armv7-unknown-linux-gnueabihf";
+        assert_eq!(
+            Target::for_cross_compile("armv7-unknown-linux-musleabihf", host).unwrap(),
+            None
+        );
+        assert_eq!(
+            Target::for_cross_compile("armv7-unknown-linux-musleabi", host).unwrap(),
+            Some(Target::ARMV5)
+        );
+        assert_eq!(
+            Target::for_cross_compile("armv7-unknown-linux-gnueabi", host).unwrap(),
+            Some(Target::ARMV5)
+        );
+    }
+
+    #[test]
+    fn android_triples() {
+        let triples = [
+            ("aarch64-linux-android", Some(Target::ARMV8)),
+            ("arm-linux-androideabi", Some(Target::ARMV5)),
+            ("armv7-linux-androideabi", Some(Target::ARMV7)),
+            ("thumbv7neon-linux-androideabi", Some(Target::ARMV7)),
+            ("i686-linux-android", Some(Target::SSE_GENERIC)),
+            ("x86_64-linux-android", Some(Target::SSE_GENERIC)),
+        ];
+        for (triple, target) in triples {
+            assert_eq!(Target::from_triple(triple), target, "{}", triple);
+        }
+    }
+
+    #[test]
+    fn vendor_triples() {
+        assert_eq!(
+            Target::from_triple("aarch64-apple-darwin"),
+            Some(Target::VORTEX)
+        );
+        assert_eq!(
+            Target::from_triple("x86_64h-apple-darwin"),
+            Some(Target::HASWELL)
+        );
+        assert_eq!(
+            Target::from_triple("powerpc64-ibm-aix"),
+            Some(Target::POWER7)
+        );
+        assert_eq!(Target::from_triple("wasm32-unknown-unknown"), None);
+    }
+
+    #[test]
+    fn rustflags_target_cpu() {
+        assert_eq!(target_cpu(""), None);
+        assert_eq!(target_cpu("-C\x1ftarget-cpu=znver3"), Some("znver3"));
+        assert_eq!(
+            target_cpu("-Ctarget-cpu=haswell\x1f--codegen=target-cpu=skylake"),
+            Some("skylake")
+        );
+        assert_eq!(target_cpu("-C\x1fopt-level=3"), None);
+    }
+
+    #[test]
+    fn rustc_target() {
+        let mut cfg = Configure::default();
+        let features = ["avx", "avx2", "fma", "sse3", "sse4.2", "ssse3"];
+        let decision = cfg.apply_rustc_target(
+            "x86_64-unknown-linux-gnu",
+            "x86_64",
+            Some("znver3"),
+            &features,
+        );
+        assert_eq!(cfg.target, Some(Target::ZEN));
+        assert_eq!(cfg.no_avx512, Some(true));
+        assert_eq!(cfg.no_avx2, Some(false));
+        assert_eq!(
+            decision,
+            "TARGET=ZEN for target-cpu=znver3, NO_AVX512=1, NO_AVX2=0"
+        );
+
+        // `-C target-feature=+avx512f,...` without `-C target-cpu`
+        let mut cfg = Configure::default();
+        let features = [
+            "avx", "avx2", "avx512bw", "avx512dq", "avx512f", "avx512vl", "fma",
+        ];
+        cfg.apply_rustc_target("x86_64-unknown-linux-gnu", "x86_64", None, &features);
+        assert_eq!(cfg.target, Some(Target::SKYLAKEX));
+        assert_eq!(cfg.no_avx512, Some(false));
+
+        // Baseline of the architecture
+        let mut cfg = Configure::default();
+        cfg.apply_rustc_target("aarch64-unknown-linux-gnu", "aarch64", None, &["neon"]);
+        assert_eq!(cfg.target, Some(Target::ARMV8));
+        assert_eq!(cfg.no_avx512, None);
+
+        // Explicit options are kept
+        let mut cfg = Configure {
+            target: Some(Target::HASWELL),
+            no_avx2: Some(true),
+            ..Default::default()
+        };
+        cfg.apply_rustc_target(
+            "x86_64-unknown-linux-gnu",
+            "x86_64",
+            Some("znver3"),
+            &["avx2"],
+        );
+        assert_eq!(cfg.target, Some(Target::HASWELL));
+        assert_eq!(cfg.no_avx2, Some(true));
+
+        let mut cfg = Configure::default();
+        cfg.apply_rustc_target("x86_64-unknown-linux-gnu", "x86_64", Some("native"), &[]);
+        assert_eq!(cfg.target, None);
+    }
+}
@@ -0,0 +1,480 @@
+//! Download OpenBLAS source from a local HTTP server standing in for GitHub and mirrors
+
+use openblas_build::{error::Error, *};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("test_build")
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Create a tarball of dummy source tree
+fn dummy_tarball(dir: &Path) -> PathBuf {
+    let path = dir.join(format!("OpenBLAS-{}.tar.gz", OPENBLAS_VERSION));
+    let gz = flate2::write::GzEncoder::new(
+        fs::File::create(&path).unwrap(),
+        flate2::Compression::default(),
+    );
+    let mut ar = tar::Builder::new(gz);
+    let content = b"all:\n";
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    ar.append_data(
+        &mut header,
+        format!("OpenBLAS-{}/Makefile", OPENBLAS_VERSION),
+        &content[..],
+    )
+    .unwrap();
+    // Incompressible content to make the tarball large enough to be sent in several packets
+    let mut x: u32 = 1;
+    let noise: Vec<u8> = (0..(1 << 18))
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(noise.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    ar.append_data(
+        &mut header,
+        format!("OpenBLAS-{}/noise.bin", OPENBLAS_VERSION),
+        &noise[..],
+    )
+    .unwrap();
+    ar.into_inner().unwrap().finish().unwrap();
+    path
+}
+
+fn sha256sum(path: &Path) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(fs::read(path).unwrap())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Serve `tarball` under `/mirror/` and `/corrupt/` (with broken content), and 404 otherwise.
+///
+/// `/flaky/` fails with 503 at first, then drops the connection in the middle of content,
+/// and finally sends the rest of content for a range request.
+///
+/// Returns the base URL, e.g. `http://127.0.0.1:12345`
+fn serve(tarball: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let content = fs::read(tarball).unwrap();
+    thread::spawn(move || {
+        let mut flaky_count = 0;
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut range_start = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(range) = line.trim().to_lowercase().strip_prefix("range: bytes=") {
+                    range_start = range.trim_end_matches('-').parse::<usize>().ok();
+                }
+            }
+            let path = request_line.split(' ').nth(1).unwrap_or_default();
+            if path.starts_with("/flaky/") {
+                flaky_count += 1;
+                match (flaky_count, range_start) {
+                    (1, _) => {
+                        write!(stream, "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                    }
+                    (_, None) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            content.len()
+                        )
+                        .unwrap();
+                        stream.write_all(&content[..content.len() / 2]).unwrap();
+                    }
+                    (_, Some(start)) => {
+                        write!(stream, "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n", content.len() - start, start, content.len() - 1, content.len()).unwrap();
+                        stream.write_all(&content[start..]).unwrap();
+                    }
+                }
+                continue;
+            }
+            let (status, body) = if path.starts_with("/mirror/") {
+                ("200 OK", content.clone())
+            } else if path.starts_with("/corrupt/") {
+                ("200 OK", b"corrupted".to_vec())
+            } else {
+                ("404 Not Found", Vec::new())
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn download_tarball() {
+    let root = test_dir("download_tarball");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let source = Source::Tarball(tarball);
+
+    let err = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("mismatch"),
+        Some("0000"),
+        &Network::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::ChecksumMismatch { .. })
+    ));
+
+    let dest = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap();
+    assert!(dest.join("Makefile").exists());
+    // The tree extracted from the same tarball is reused
+    fs::write(dest.join("stale"), "").unwrap();
+    let out_dir = root.join("out");
+    let network = Network::default();
+    assert_eq!(
+        download(&source, OPENBLAS_VERSION, &out_dir, Some(&sha256), &network).unwrap(),
+        dest
+    );
+    assert!(dest.join("stale").exists());
+    // The tree extracted from an unknown tarball, e.g. a patched one, is extracted again
+    fs::write(
+        out_dir.join(format!("OpenBLAS-{}.sha256", OPENBLAS_VERSION)),
+        "0".repeat(64),
+    )
+    .unwrap();
+    download(&source, OPENBLAS_VERSION, &out_dir, Some(&sha256), &network).unwrap();
+    assert!(dest.join("Makefile").exists());
+    assert!(!dest.join("stale").exists());
+    assert_eq!(
+        download(
+            &Source::Directory(dest.clone()),
+            OPENBLAS_VERSION,
+            &root,
+            None,
+            &Network::default(),
+        )
+        .unwrap(),
+        dest
+    );
+
+    let err = download(
+        &source,
+        "0.0.1",
+        &root.join("unknown"),
+        None,
+        &Network::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::UnknownVersion { .. })
+    ));
+}
+
+#[test]
+fn download_pinned_version() {
+    let root = test_dir("download_pinned_version");
+    let source = Source::Tarball(dummy_tarball(&root));
+    // The default version must be downloadable without `OPENBLAS_SOURCE_SHA256`
+    assert!(
+        openblas_source_sha256(OPENBLAS_VERSION).is_ok(),
+        "No SHA-256 is pinned for the default version {}",
+        OPENBLAS_VERSION
+    );
+    // Without the checksum given explicitly, the dummy tarball is verified
+    // against the pinned checksum, and is never extracted
+    for (version, pinned) in OPENBLAS_VERSIONS {
+        let err = download(
+            &source,
+            version,
+            &root.join(version),
+            None,
+            &Network::default(),
+        )
+        .unwrap_err();
+        match (pinned, err.downcast_ref::<Error>()) {
+            (Some(pinned), Some(Error::ChecksumMismatch { expected, .. })) => {
+                assert_eq!(expected, pinned)
+            }
+            (_, e) => panic!("Unexpected result for {}: {:?}", version, e),
+        }
+        assert!(!root
+            .join(version)
+            .join(format!("OpenBLAS-{}", version))
+            .exists());
+    }
+}
+
+#[test]
+fn cache_tarball() {
+    let root = test_dir("cache_tarball");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let cache_dir = root.join("sources");
+
+    let source = Source::Urls(vec![format!("file://{}", tarball.display())]);
+    let cached = cache_source(
+        &source,
+        OPENBLAS_VERSION,
+        &cache_dir,
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap();
+    let expected = cache_dir.join(format!("{}-{}.tar.gz", OPENBLAS_VERSION, sha256));
+    assert_eq!(cached, Source::Tarball(expected.clone()));
+
+    // Cached tarball is used without accessing the URL
+    fs::remove_file(&tarball).unwrap();
+    assert_eq!(
+        cache_source(
+            &source,
+            OPENBLAS_VERSION,
+            &cache_dir,
+            Some(&sha256),
+            &Network::default()
+        )
+        .unwrap(),
+        cached
+    );
+    // Cached tarball with another checksum, e.g. from another URL, is not used
+    assert!(cache_source(
+        &source,
+        OPENBLAS_VERSION,
+        &cache_dir,
+        Some(&"0".repeat(64)),
+        &Network::default()
+    )
+    .is_err());
+    let dest = download(
+        &cached,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap();
+    assert!(dest.join("Makefile").exists());
+    // Cache is kept after extraction
+    assert!(expected.exists());
+}
+
+#[test]
+fn download_from_mirrors() {
+    let root = test_dir("download_from_mirrors");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let base = serve(&tarball);
+    let file_name = tarball.file_name().unwrap().to_str().unwrap();
+
+    let source = Source::Urls(vec![
+        format!("{}/missing/{}", base, file_name),
+        format!("{}/corrupt/{}", base, file_name),
+        format!("{}/mirror/{}", base, file_name),
+    ]);
+    let out_dir = root.join("out");
+    let dest = download(
+        &source,
+        OPENBLAS_VERSION,
+        &out_dir,
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap();
+    assert_eq!(dest, out_dir.join(format!("OpenBLAS-{}", OPENBLAS_VERSION)));
+    assert!(dest.join("Makefile").exists());
+    // Downloaded tarball is removed after extraction
+    assert!(!out_dir.join(file_name).exists());
+}
+
+#[test]
+fn download_with_retry_and_resume() {
+    let root = test_dir("download_with_retry_and_resume");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let base = serve(&tarball);
+    let file_name = tarball.file_name().unwrap().to_str().unwrap();
+
+    let source = Source::Urls(vec![format!("{}/flaky/{}", base, file_name)]);
+    let network = Network {
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let dest = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &network,
+    )
+    .unwrap();
+    assert!(dest.join("Makefile").exists());
+}
+
+#[test]
+fn download_without_retry() {
+    let root = test_dir("download_without_retry");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let base = serve(&tarball);
+    let file_name = tarball.file_name().unwrap().to_str().unwrap();
+
+    let source = Source::Urls(vec![format!("{}/flaky/{}", base, file_name)]);
+    let network = Network {
+        retries: 0,
+        ..Default::default()
+    };
+    assert!(download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &network,
+    )
+    .is_err());
+}
+
+#[test]
+fn download_corrupted() {
+    let root = test_dir("download_corrupted");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let base = serve(&tarball);
+    let file_name = tarball.file_name().unwrap().to_str().unwrap();
+
+    let source = Source::Urls(vec![format!("{}/corrupt/{}", base, file_name)]);
+    let err = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn download_from_file_url() {
+    let root = test_dir("download_from_file_url");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+
+    let source = Source::Urls(vec![format!("file://{}", tarball.display())]);
+    let dest = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root.join("out"),
+        Some(&sha256),
+        &Network::default(),
+    )
+    .unwrap();
+    assert!(dest.join("Makefile").exists());
+    // Original file is kept
+    assert!(tarball.exists());
+}
+
+#[test]
+fn download_unsupported_url() {
+    let root = test_dir("download_unsupported_url");
+    let source = Source::Urls(vec!["ftp://example.com/OpenBLAS.tar.gz".into()]);
+    let err = download(
+        &source,
+        OPENBLAS_VERSION,
+        &root,
+        Some("0000"),
+        &Network::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::UnsupportedUrl { .. })
+    ));
+}
+
+#[test]
+fn cache_resume_part() {
+    let root = test_dir("cache_resume_part");
+    let tarball = dummy_tarball(&root);
+    let sha256 = sha256sum(&tarball);
+    let base = serve(&tarball);
+    let file_name = tarball.file_name().unwrap().to_str().unwrap();
+
+    let source = Source::Urls(vec![format!("{}/flaky/{}", base, file_name)]);
+    let network = Network {
+        retries: 1,
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let cache_dir = root.join("sources");
+    // Fails with 503, and then the connection is dropped in the middle of content
+    assert!(cache_source(
+        &source,
+        OPENBLAS_VERSION,
+        &cache_dir,
+        Some(&sha256),
+        &network
+    )
+    .is_err());
+    let part = cache_dir.join(format!(".{}-{}.tar.gz.part", OPENBLAS_VERSION, sha256));
+    assert!(part.exists());
+
+    // The next build resumes the partially downloaded file
+    let cached = cache_source(
+        &source,
+        OPENBLAS_VERSION,
+        &cache_dir,
+        Some(&sha256),
+        &network,
+    )
+    .unwrap();
+    assert_eq!(
+        cached,
+        Source::Tarball(cache_dir.join(format!("{}-{}.tar.gz", OPENBLAS_VERSION, sha256)))
+    );
+    assert!(!part.exists());
+}
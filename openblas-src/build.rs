@@ -1,4 +1,5 @@
-use std::{env, path::*, process::Command};
+use openblas_build::{BuildLock, CacheKey, ConfigHeader, Interface, Network, Source, Threading};
+use std::{env, path::*, process::Command, time::Duration};
 
 #[allow(unused)]
 fn run(command: &mut Command) {
@@ -16,7 +17,28 @@ fn run(command: &mut Command) {
 }
 
 fn feature_enabled(feature: &str) -> bool {
-    env::var(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_ok()
+    env::var(format!(
+        "CARGO_FEATURE_{}",
+        feature.to_uppercase().replace('-', "_")
+    ))
+    .is_ok()
+}
+
+/// musl targets, e.g. `x86_64-unknown-linux-musl`
+fn is_musl() -> bool {
+    env::var("CARGO_CFG_TARGET_ENV").is_ok_and(|env| env == "musl")
+}
+
+/// Link OpenBLAS statically by `static` feature, or always for musl targets
+/// since the shared libraries of the build host are linked to glibc
+fn link_static() -> bool {
+    feature_enabled("static") || is_musl()
+}
+
+/// Search paths of glibc toolchain, e.g. `/usr/lib/gcc/x86_64-linux-gnu/11`,
+/// which must not be used for musl targets
+fn is_glibc_path(path: &Path) -> bool {
+    path.to_string_lossy().contains("-linux-gnu")
 }
 
 /// Add path where pacman (on msys2) install OpenBLAS
@@ -27,7 +49,7 @@ fn feature_enabled(feature: &str) -> bool {
 /// - But we have to specify them using `-L` in **Windows manner**
 ///   - msys2 `/` is `C:\msys64\` in Windows by default install
 ///   - It can be convert using `cygpath` command
-fn windows_gnu_system() {
+fn windows_gnu_system() -> PathBuf {
     let include_path = String::from_utf8(
         Command::new("cygpath")
             .arg("-w")
@@ -53,6 +75,7 @@ fn windows_gnu_system() {
     println!("cargo:rustc-link-search={}", lib_path);
     println!("cargo:INCLUDE={}", include_path);
     println!("cargo:LIBRARY={}", lib_path);
+    PathBuf::from(include_path.trim())
 }
 
 /// Use vcpkg for msvc "system" feature
@@ -70,7 +93,7 @@ fn windows_msvc_system() {
 }
 
 /// Add linker flag (`-L`) to path where brew installs OpenBLAS
-fn macos_system() {
+fn macos_system() -> PathBuf {
     fn brew_prefix(target: &str) -> PathBuf {
         let out = Command::new("brew")
             .arg("--prefix")
@@ -88,30 +111,180 @@ fn macos_system() {
     println!("cargo:rustc-link-search={}/lib", libomp.display());
     println!("cargo:INCLUDE={}", openblas.join("include").display());
     println!("cargo:LIBRARY={}", openblas.join("lib").display());
+    openblas.join("include")
+}
+
+/// Export the properties of system OpenBLAS found from its headers in `include_dirs`
+/// or the default include directories.
+///
+/// `VERSION` is exported from `openblas_config.h` unless `version_known`.
+fn system_metadata(include_dirs: &[PathBuf], version_known: bool, interface: Interface) {
+    let mut default_dirs: Vec<PathBuf> = [
+        "/usr/include",
+        "/usr/local/include",
+        "/usr/include/openblas",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    // Debian and Ubuntu install the headers of each threading model,
+    // e.g. `/usr/include/x86_64-linux-gnu/openblas-pthread`, ordered by their priorities
+    if let Some(multiarch) = debian_multiarch() {
+        let prefix = match interface {
+            Interface::LP64 => "openblas",
+            Interface::ILP64 => "openblas64",
+        };
+        for threading in ["pthread", "openmp", "serial"] {
+            default_dirs.push(
+                Path::new("/usr/include")
+                    .join(&multiarch)
+                    .join(format!("{}-{}", prefix, threading)),
+            );
+        }
+    }
+    // Headers of the other integer interface describe another library
+    let headers: Vec<(PathBuf, ConfigHeader)> = include_dirs
+        .iter()
+        .cloned()
+        .chain(default_dirs)
+        .filter_map(|dir| {
+            Some((
+                dir.clone(),
+                ConfigHeader::new(dir.join("openblas_config.h")).ok()?,
+            ))
+        })
+        .collect();
+    let (include_dir, header) = match headers
+        .iter()
+        .find(|(_, header)| header.ilp64() == (interface == Interface::ILP64))
+    {
+        Some(found) => found,
+        None => {
+            if let Some((dir, _)) = headers.first() {
+                println!(
+                    "cargo:warning=OpenBLAS: openblas_config.h in {} is not for the {:?} interface",
+                    dir.display(),
+                    interface
+                );
+            }
+            return;
+        }
+    };
+    if let (false, Some(version)) = (version_known, header.version()) {
+        println!("cargo:VERSION={}", version);
+    }
+    // The threading model other than OpenMP is not recorded in the header,
+    // but is known from the directory name on Debian, e.g. `openblas-serial`
+    let dir_name = include_dir.file_name().and_then(|name| name.to_str());
+    let threading = if header.openmp() {
+        Some("openmp")
+    } else if dir_name.is_some_and(|name| name.ends_with("-pthread")) {
+        Some("pthreads")
+    } else if dir_name.is_some_and(|name| name.ends_with("-serial")) {
+        Some("serial")
+    } else {
+        None
+    };
+    if let Some(threading) = threading {
+        println!("cargo:THREADING={}", threading);
+    }
+    if let Some(core) = header.core() {
+        println!("cargo:CORE={}", core);
+        println!("cargo:LIBCORE={}", core.to_lowercase());
+    }
+    for (key, file) in [
+        ("CBLAS", "cblas.h"),
+        ("LAPACK", "lapack.h"),
+        ("LAPACKE", "lapacke.h"),
+    ] {
+        println!("cargo:{}={}", key, flag(include_dir.join(file).exists()));
+    }
+}
+
+/// Debian multiarch tuple of the target, e.g. `x86_64-linux-gnu` or `arm-linux-gnueabihf`
+fn debian_multiarch() -> Option<String> {
+    let target = env::var("TARGET").ok()?;
+    let components: Vec<&str> = target.split('-').collect();
+    match components[..] {
+        [arch, _, "linux", env] if env.starts_with("gnu") => {
+            let arch = match arch {
+                "i586" | "i686" => "i386",
+                "armv5te" | "armv7" | "thumbv7neon" => "arm",
+                "riscv64gc" => "riscv64",
+                arch => arch,
+            };
+            Some(format!("{}-linux-{}", arch, env))
+        }
+        _ => None,
+    }
+}
+
+/// Boolean value of metadata
+fn flag(value: bool) -> u8 {
+    value as u8
 }
 
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(openblas_ilp64)");
     if env::var("DOCS_RS").is_ok() {
         return;
     }
-    let link_kind = if feature_enabled("static") {
-        "static"
-    } else {
-        "dylib"
-    };
-    if feature_enabled("system") {
+    let link_kind = if link_static() { "static" } else { "dylib" };
+    let interface = interface();
+    println!("cargo:INTERFACE={:?}", interface);
+    println!(
+        "cargo:LINK={}",
+        if link_kind == "static" {
+            "static"
+        } else {
+            "dynamic"
+        }
+    );
+    println!(
+        "cargo:SOURCE={}",
+        if feature_enabled("system") {
+            "system"
+        } else {
+            "build"
+        }
+    );
+    // `blasint` in `openblas_src::ffi` follows the integer interface
+    if interface == Interface::ILP64 {
+        println!("cargo:rustc-cfg=openblas_ilp64");
+    }
+    let lib_name = if feature_enabled("system") {
+        // Distributions ship ILP64 OpenBLAS under different names,
+        // e.g. `openblas64.pc` on Debian and `libopenblas64_.so` on Fedora.
+        let (pkg_names, lib_name): (&[&str], _) = match interface {
+            Interface::LP64 => (&["openblas"], "openblas"),
+            Interface::ILP64 => (&["openblas64", "openblas64_"], "openblas64_"),
+        };
         // Use pkg-config to find OpenBLAS
-        if pkg_config::Config::new()
-            .statik(feature_enabled("static"))
-            .probe("openblas")
-            .is_ok()
-        {
-            return;
+        for pkg_name in pkg_names {
+            if let Ok(lib) = pkg_config::Config::new()
+                .statik(link_static())
+                .probe(pkg_name)
+            {
+                println!("cargo:VERSION={}", lib.version);
+                // pkg-config omits the system include directories from `include_paths`
+                let mut include_dirs = lib.include_paths;
+                if include_dirs.is_empty() {
+                    include_dirs.extend(
+                        pkg_config::get_variable(pkg_name, "includedir")
+                            .ok()
+                            .filter(|dir| !dir.is_empty())
+                            .map(PathBuf::from),
+                    );
+                }
+                system_metadata(&include_dirs, false, interface);
+                return;
+            }
         }
 
+        let mut include_dirs = Vec::new();
         if cfg!(target_os = "windows") {
             if cfg!(target_env = "gnu") {
-                windows_gnu_system();
+                include_dirs.push(windows_gnu_system());
             } else if cfg!(target_env = "msvc") {
                 windows_msvc_system();
             } else {
@@ -122,38 +295,136 @@ fn main() {
             }
         }
         if cfg!(target_os = "macos") {
-            macos_system();
+            include_dirs.push(macos_system());
         }
+        system_metadata(&include_dirs, true, interface);
+        lib_name
     } else {
         if cfg!(target_env = "msvc") {
             panic!(
                 "Non-vcpkg builds are not supported on Windows. You must use the 'system' feature."
             )
         }
-        build();
+        build(interface);
+        "openblas"
+    };
+    println!("cargo:rustc-link-lib={}={}", link_kind, lib_name);
+}
+
+/// Read a numeric variable, e.g. `OPENBLAS_NUM_PARALLEL=4`
+fn env_number(name: &str) -> Option<u32> {
+    println!("cargo:rerun-if-env-changed={}", name);
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("${} must be a non-negative integer: {}", name, value)),
+    )
+}
+
+/// Read an on/off variable, e.g. `OPENBLAS_NO_AFFINITY=1`
+fn env_switch(name: &str) -> Option<bool> {
+    println!("cargo:rerun-if-env-changed={}", name);
+    match env::var(name).ok()?.as_str() {
+        "1" => Some(true),
+        "0" => Some(false),
+        value => panic!("${} must be 0 or 1: {}", name, value),
+    }
+}
+
+/// Integer interface selected by `ilp64` feature or `$OPENBLAS_INTERFACE64`
+fn interface() -> Interface {
+    if feature_enabled("ilp64") || env_switch("OPENBLAS_INTERFACE64").unwrap_or(false) {
+        Interface::ILP64
+    } else {
+        Interface::LP64
     }
-    println!("cargo:rustc-link-lib={}=openblas", link_kind);
 }
 
 /// Build OpenBLAS using openblas-build crate
-fn build() {
+fn build(interface: Interface) {
     println!("cargo:rerun-if-env-changed=OPENBLAS_TARGET");
     println!("cargo:rerun-if-env-changed=OPENBLAS_CC");
     println!("cargo:rerun-if-env-changed=OPENBLAS_HOSTCC");
     println!("cargo:rerun-if-env-changed=OPENBLAS_FC");
     println!("cargo:rerun-if-env-changed=OPENBLAS_RANLIB");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_DYNAMIC_LIST");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_VERSION");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_SOURCE_SHA256");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_SOURCE_URL");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_SOURCE_MIRRORS");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_SOURCE_DIR");
+    println!("cargo:rerun-if-env-changed=OPENBLAS_SOURCE_TARBALL");
+    println!("cargo:rerun-if-env-changed=CARGO_NET_OFFLINE");
+    // Variables in the cache key and the fingerprint of the build besides `OPENBLAS_*` above
+    for name in openblas_build::CACHE_KEY_VARIABLES {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
     let mut cfg = openblas_build::Configure::default();
+    if let Ok(version) = env::var("OPENBLAS_VERSION") {
+        cfg.version = version;
+    }
     if !feature_enabled("cblas") {
         cfg.no_cblas = true;
     }
     if !feature_enabled("lapacke") {
         cfg.no_lapacke = true;
     }
-    if feature_enabled("static") {
+    // LAPACKE is not built without LAPACK either
+    cfg.no_lapack = env_switch("OPENBLAS_NO_LAPACK").unwrap_or(false);
+    if link_static() {
         cfg.no_shared = true;
     } else {
         cfg.no_static = true;
     }
+    cfg.interface = interface;
+    // Build all precisions if none of them is enabled,
+    // e.g. by `default-features = false`
+    let precisions = openblas_build::Precisions {
+        single: feature_enabled("single"),
+        double: feature_enabled("double"),
+        complex: feature_enabled("complex"),
+        complex16: feature_enabled("complex16"),
+    };
+    if precisions.single || precisions.double || precisions.complex || precisions.complex16 {
+        cfg.precisions = precisions;
+    }
+    if feature_enabled("bfloat16") {
+        cfg.build_bfloat16 = true;
+    }
+    if feature_enabled("hfloat16") {
+        cfg.build_hfloat16 = true;
+    }
+    if feature_enabled("dynamic-arch") {
+        cfg.dynamic_arch = true;
+    }
+    if let Ok(list) = env::var("OPENBLAS_DYNAMIC_LIST") {
+        if !cfg.dynamic_arch {
+            println!("cargo:warning=OPENBLAS_DYNAMIC_LIST is ignored since `dynamic-arch` feature is not enabled");
+        }
+        cfg.dynamic_list = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|target| !target.is_empty())
+            .map(|target| {
+                target.parse().unwrap_or_else(|_| {
+                    panic!(
+                        "Unsupported target {} is specified by $OPENBLAS_DYNAMIC_LIST",
+                        target
+                    )
+                })
+            })
+            .collect();
+    }
+    match threading_features().as_slice() {
+        [] => {}
+        ["openmp"] => cfg.threading = Threading::OpenMP,
+        ["pthreads"] => cfg.threading = Threading::Pthreads,
+        ["serial"] => cfg.threading = Threading::Serial,
+        features => panic!(
+            "Features {:?} are mutually exclusive. Enable only one of `openmp`, `pthreads` and `serial`.",
+            features
+        ),
+    }
     if let Ok(target) = env::var("OPENBLAS_TARGET") {
         cfg.target = Some(
             target
@@ -168,56 +439,138 @@ fn build() {
     cfg.compilers.fc = env::var("OPENBLAS_FC").ok();
     cfg.compilers.ranlib = env::var("OPENBLAS_RANLIB").ok();
 
-    let output = if feature_enabled("cache") {
-        use std::{
-            collections::hash_map::DefaultHasher,
-            hash::{Hash, Hasher},
-        };
-        // Build OpenBLAS on user's data directory.
-        // See https://docs.rs/dirs/5.0.1/dirs/fn.data_dir.html
-        //
-        // On Linux, `data_dir` returns `$XDG_DATA_HOME` or `$HOME/.local/share`.
-        // This build script creates a directory based on the hash value of `cfg`,
-        // i.e. `$XDG_DATA_HOME/openblas_build/[Hash of cfg]`, and build OpenBLAS there.
-        //
-        // This build will be shared among several projects using openblas-src crate.
-        // It makes users not to build OpenBLAS in every `cargo build`.
-        let mut hasher = DefaultHasher::new();
-        cfg.hash(&mut hasher);
-
-        dirs::data_dir()
-            .expect("Cannot get user's data directory")
-            .join("openblas_build")
-            .join(format!("{:x}", hasher.finish()))
-    } else {
-        PathBuf::from(env::var("OUT_DIR").unwrap())
-    };
-    let source = openblas_build::download(&output).unwrap();
+    // `NUM_THREADS` is renamed to `OPENBLAS_BUILD_NUM_THREADS`
+    // since `OPENBLAS_NUM_THREADS` is read by OpenBLAS at runtime.
+    cfg.num_threads = env_number("OPENBLAS_BUILD_NUM_THREADS");
+    cfg.num_parallel = env_number("OPENBLAS_NUM_PARALLEL");
+    cfg.buffer_size = env_number("OPENBLAS_BUFFERSIZE");
+    cfg.gemm_multithread_threshold = env_number("OPENBLAS_GEMM_MULTITHREAD_THRESHOLD");
+    cfg.max_stack_alloc = env_number("OPENBLAS_MAX_STACK_ALLOC");
+    cfg.use_locking = env_switch("OPENBLAS_USE_LOCKING");
+    cfg.no_affinity = env_switch("OPENBLAS_NO_AFFINITY");
+    cfg.consistent_fpcsr = env_switch("OPENBLAS_CONSISTENT_FPCSR");
+    cfg.use_tls = env_switch("OPENBLAS_USE_TLS");
+    cfg.no_avx512 = env_switch("OPENBLAS_NO_AVX512");
+    cfg.no_avx2 = env_switch("OPENBLAS_NO_AVX2");
+    cfg.build_relapack = env_switch("OPENBLAS_BUILD_RELAPACK");
+    cfg.small_matrix_opt = env_switch("OPENBLAS_SMALL_MATRIX_OPT");
+    // Select `TARGET` from `-C target-cpu` and target features of rustc
+    // for the machine the executables are deployed on, instead of the build host
+    if env_switch("OPENBLAS_TARGET_FROM_RUSTC").unwrap_or(false) {
+        cfg.target_from_rustc();
+    }
 
-    // If OpenBLAS is build as shared, user of openblas-src will have to find `libopenblas.so` at runtime.
+    // Build OpenBLAS on user's data directory with `cache` feature.
+    // See https://docs.rs/dirs/6.0.0/dirs/fn.data_dir.html
     //
-    // `cargo run` appends the link paths to `LD_LIBRARY_PATH` specified by `cargo:rustc-link-search`,
-    // and user's crate can find it then.
-    //
-    // However, when user try to run it directly like `./target/release/user_crate_exe`, it will say
-    // "error while loading shared libraries: libopenblas.so: cannot open shared object file: No such file or directory".
+    // On Linux, `data_dir` returns `$XDG_DATA_HOME` or `$HOME/.local/share`.
+    // This build script creates a directory based on the stable hash of everything affecting the build,
+    // i.e. `$XDG_DATA_HOME/openblas_build/[SHA-256 of key.json]`, and build OpenBLAS there.
+    // The key is written into `key.json` in the directory for inspection.
+    // The source tarball is shared among them in `$XDG_DATA_HOME/openblas_build/sources`.
     //
-    // Be sure that `cargo:warning` is shown only when openblas-src is build as path dependency...
-    // https://doc.rust-lang.org/cargo/reference/build-scripts.html#cargowarningmessage
-    if !feature_enabled("static") {
-        let ld_name = if cfg!(target_os = "macos") {
-            "DYLD_LIBRARY_PATH"
-        } else {
-            "LD_LIBRARY_PATH"
-        };
-        println!(
-            "cargo:warning=OpenBLAS is built as a shared library. You need to set {}={}",
-            ld_name,
-            source.display()
-        );
+    // This build will be shared among several projects using openblas-src crate.
+    // It makes users not to build OpenBLAS in every `cargo build`.
+    let cache_root = if feature_enabled("cache") {
+        Some(openblas_build::cache_root().expect("Cannot get user's data directory"))
+    } else {
+        None
+    };
+    let key = cache_root
+        .as_ref()
+        .map(|_| CacheKey::new(&cfg).unwrap_or_else(|e| panic!("Invalid configuration: {:?}", e)));
+    let output = match (&cache_root, &key) {
+        (Some(cache_root), Some(key)) => {
+            let output = cache_root.join(key.digest());
+            std::fs::create_dir_all(&output).unwrap();
+            output
+        }
+        _ => PathBuf::from(env::var("OUT_DIR").unwrap()),
+    };
+    let source = match (
+        env::var_os("OPENBLAS_SOURCE_DIR"),
+        env::var_os("OPENBLAS_SOURCE_TARBALL"),
+    ) {
+        (None, None) => {
+            // URL templates like `https://mirror.example.com/OpenBLAS-{version}.tar.gz`
+            let url = env::var("OPENBLAS_SOURCE_URL")
+                .unwrap_or_else(|_| openblas_build::openblas_source_url("{version}"));
+            let mirrors = env::var("OPENBLAS_SOURCE_MIRRORS").unwrap_or_default();
+            Source::Urls(
+                std::iter::once(url.as_str())
+                    .chain(mirrors.split_whitespace())
+                    .map(|template| template.replace("{version}", &cfg.version))
+                    .collect(),
+            )
+        }
+        (Some(dir), None) => Source::Directory(dir.into()),
+        (None, Some(tarball)) => {
+            println!("cargo:rerun-if-changed={}", Path::new(&tarball).display());
+            Source::Tarball(tarball.into())
+        }
+        (Some(_), Some(_)) => {
+            panic!("Only one of $OPENBLAS_SOURCE_DIR and $OPENBLAS_SOURCE_TARBALL can be set")
+        }
+    };
+    let source_sha256 = env::var("OPENBLAS_SOURCE_SHA256").ok();
+    let mut network = Network::default();
+    if let Some(secs) = env_number("OPENBLAS_DOWNLOAD_CONNECT_TIMEOUT") {
+        network.connect_timeout = Duration::from_secs(secs.into());
+    }
+    if let Some(secs) = env_number("OPENBLAS_DOWNLOAD_TIMEOUT") {
+        network.timeout = Duration::from_secs(secs.into());
     }
+    if let Some(retries) = env_number("OPENBLAS_DOWNLOAD_RETRIES") {
+        network.retries = retries;
+    }
+    // Lock the build directory shared with other processes until the build finishes
+    let _lock = if let Some(key) = &key {
+        let timeout = env_number("OPENBLAS_CACHE_LOCK_TIMEOUT").unwrap_or(3600);
+        let lock = BuildLock::acquire(output.join(".lock"), Duration::from_secs(timeout.into()))
+            .unwrap_or_else(|e| panic!("Failed to lock OpenBLAS build directory: {}", e));
+        std::fs::write(output.join("key.json"), key.to_json()).unwrap();
+        Some(lock)
+    } else {
+        None
+    };
+    let source = match &cache_root {
+        Some(cache_root) => openblas_build::cache_source(
+            &source,
+            &cfg.version,
+            &cache_root.join("sources"),
+            source_sha256.as_deref(),
+            &network,
+        )
+        .unwrap_or_else(|e| panic!("Failed to get OpenBLAS source: {:?}", e)),
+        None => source,
+    };
+    let source = match openblas_build::download(
+        &source,
+        &cfg.version,
+        &output,
+        source_sha256.as_deref(),
+        &network,
+    ) {
+        Ok(source) => source,
+        Err(e) => panic!("Failed to get OpenBLAS source: {:?}", e),
+    };
 
-    let build_result = cfg.build(&source);
+    // OpenBLAS is built out of the source tree, which is kept pristine,
+    // and is installed into `install/include` and `install/lib`
+    let build_dir = output.join("build");
+    let prefix = output.join("install");
+    let lib_dir = prefix.join("lib");
+
+    let use_openmp = cfg.threading == Threading::OpenMP;
+    let version = cfg.version.clone();
+    // `None` if determined by OpenBLAS from the number of CPU cores
+    let threading = cfg.threading.name();
+    let num_threads = cfg.num_threads;
+    let has_cblas = !cfg.no_cblas;
+    let has_lapack = !cfg.no_lapack;
+    let has_lapacke = has_lapack && !cfg.no_lapacke;
+    let dynamic_arch = cfg.dynamic_arch;
+    let build_result = cfg.build(&source, &build_dir, &prefix);
     let make_conf = match build_result {
         Ok(c) => c,
         Err(openblas_build::error::Error::MissingCrossCompileInfo { info }) => {
@@ -231,19 +584,169 @@ fn build() {
         }
     };
 
-    println!("cargo:rustc-link-search={}", source.display());
-    println!("cargo:INCLUDE={}", source.display());
-    println!("cargo:LIBRARY={}", source.display());
+    println!("cargo:rustc-link-search={}", lib_dir.display());
+    println!("cargo:VERSION={}", version);
+    println!("cargo:INCLUDE={}", prefix.join("include").display());
+    println!("cargo:LIBRARY={}", lib_dir.display());
+    let threading = threading.unwrap_or_else(|| match num_threads.or(make_conf.num_cores) {
+        Some(n) if n > 1 => "pthreads",
+        _ => "serial",
+    });
+    println!("cargo:THREADING={}", threading);
+    println!("cargo:CBLAS={}", flag(has_cblas));
+    println!("cargo:LAPACK={}", flag(has_lapack));
+    println!("cargo:LAPACKE={}", flag(has_lapacke));
+    // LAPACK is converted into C by f2c if Fortran compiler is not found
+    let lapack_impl = match (has_lapack, make_conf.no_fortran) {
+        (false, _) => "none",
+        (true, true) => "f2c",
+        (true, false) => "fortran",
+    };
+    println!("cargo:LAPACK_IMPL={}", lapack_impl);
+    println!("cargo:CORE={}", make_conf.core);
+    println!("cargo:LIBCORE={}", make_conf.libcore);
+    println!("cargo:DYNAMIC_ARCH={}", flag(dynamic_arch));
+
+    // If OpenBLAS is build as shared, user of openblas-src will have to find `libopenblas.so` at runtime.
+    //
+    // `cargo run` appends the link paths to `LD_LIBRARY_PATH` specified by `cargo:rustc-link-search`,
+    // and user's crate can find it then.
+    //
+    // However, when user try to run it directly like `./target/release/user_crate_exe`, it will say
+    // "error while loading shared libraries: libopenblas.so: cannot open shared object file: No such file or directory".
+    //
+    // `rpath` and `rpath-origin` features embed the location of the library into the executables.
+    // Since `cargo:rustc-link-arg` only affects the targets of openblas-src itself,
+    // the rpath is also exported as `DEP_OPENBLAS_RPATH` for the build scripts of dependents.
+    //
+    // Be sure that `cargo:warning` is shown only when openblas-src is build as path dependency...
+    // https://doc.rust-lang.org/cargo/reference/build-scripts.html#cargowarningmessage
+    if !link_static() {
+        // `cfg!(target_os)` is the host of this build script
+        let is_macos = env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "macos");
+        let rpath = if feature_enabled("rpath-origin") {
+            copy_shared_libs(&lib_dir);
+            Some(if is_macos {
+                "@loader_path".to_string()
+            } else {
+                "$ORIGIN".to_string()
+            })
+        } else if feature_enabled("rpath") {
+            Some(lib_dir.display().to_string())
+        } else {
+            None
+        };
+        match rpath {
+            Some(rpath) => {
+                println!("cargo:rustc-link-arg=-Wl,-rpath,{}", rpath);
+                println!("cargo:RPATH={}", rpath);
+            }
+            None => {
+                let ld_name = if is_macos {
+                    "DYLD_LIBRARY_PATH"
+                } else {
+                    "LD_LIBRARY_PATH"
+                };
+                println!(
+                    "cargo:warning=OpenBLAS is built as a shared library. You need to set {}={}, or enable `rpath` or `rpath-origin` feature",
+                    ld_name,
+                    lib_dir.display()
+                );
+            }
+        }
+    }
+    println!(
+        "cargo:PKGCONFIG_DIR={}",
+        lib_dir.join("pkgconfig").display()
+    );
+    println!(
+        "cargo:CMAKE_DIR={}",
+        lib_dir.join("cmake/openblas").display()
+    );
     for search_path in &make_conf.c_extra_libs.search_paths {
+        if is_musl() && is_glibc_path(search_path) {
+            continue;
+        }
         println!("cargo:rustc-link-search={}", search_path.display());
     }
     for lib in &make_conf.c_extra_libs.libs {
         println!("cargo:rustc-link-lib={}", lib);
     }
     for search_path in &make_conf.f_extra_libs.search_paths {
+        if is_musl() && is_glibc_path(search_path) {
+            continue;
+        }
         println!("cargo:rustc-link-search={}", search_path.display());
     }
     for lib in &make_conf.f_extra_libs.libs {
+        if feature_enabled("static-fortran-runtime") && !SYSTEM_LIBS.contains(&lib.as_str()) {
+            match static_lib_path(&make_conf.fc, lib) {
+                Some(path) => {
+                    let dir = path.parent().unwrap();
+                    println!("cargo:rustc-link-search=native={}", dir.display());
+                    println!("cargo:rustc-link-lib=static={}", lib);
+                    continue;
+                }
+                None => println!(
+                    "cargo:warning=OpenBLAS: lib{}.a is not found by {}, and is linked dynamically",
+                    lib, make_conf.fc
+                ),
+            }
+        }
         println!("cargo:rustc-link-lib={}", lib);
     }
+    // OpenMP runtime is not listed in CEXTRALIB/FEXTRALIB,
+    // but static library requires it explicitly.
+    if use_openmp {
+        println!("cargo:rustc-link-lib={}", make_conf.openmp_lib());
+    }
+}
+
+/// Libraries of the system, which are linked dynamically even with `static-fortran-runtime` feature
+const SYSTEM_LIBS: &[&str] = &["c", "m", "dl", "rt", "pthread", "gcc_s"];
+
+/// Find static library, e.g. `libgfortran.a`, by `{compiler} -print-file-name=lib{name}.a`
+fn static_lib_path(compiler: &str, name: &str) -> Option<PathBuf> {
+    let output = Command::new(compiler)
+        .arg(format!("-print-file-name=lib{}.a", name))
+        .output()
+        .ok()?;
+    let path = PathBuf::from(String::from_utf8(output.stdout).ok()?.trim());
+    // The compiler prints the given file name as is if it is not found
+    if path.is_absolute() && path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Copy shared libraries in `lib_dir` into `target/<profile>` next to the final artifacts
+fn copy_shared_libs(lib_dir: &Path) {
+    // `OUT_DIR` is `target/<profile>/build/openblas-src-<hash>/out`
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let profile_dir = out_dir
+        .ancestors()
+        .nth(3)
+        .expect("Cannot find target directory from OUT_DIR");
+    for entry in std::fs::read_dir(lib_dir).expect("Cannot read OpenBLAS library directory") {
+        let path = entry.unwrap().path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.starts_with("libopenblas") && (name.contains(".so") || name.ends_with(".dylib")) {
+            std::fs::copy(&path, profile_dir.join(name)).unwrap_or_else(|e| {
+                panic!("Cannot copy {} to {}: {}", name, profile_dir.display(), e)
+            });
+        }
+    }
+}
+
+/// Threading features enabled by user
+fn threading_features() -> Vec<&'static str> {
+    ["openmp", "pthreads", "serial"]
+        .iter()
+        .copied()
+        .filter(|feature| feature_enabled(feature))
+        .collect()
 }
@@ -1,3 +1,171 @@
 // Use `CARGO_PKG_README` because of https://github.com/rust-lang/cargo/issues/11597
 #![doc = include_str!(concat!("../", env!("CARGO_PKG_README")))]
 #![no_std]
+
+/// Declarations of OpenBLAS extensions which are not a part of BLAS and LAPACK
+///
+/// Usual BLAS and LAPACK routines are provided by
+/// [blas-sys](https://crates.io/crates/blas-sys),
+/// [lapack-sys](https://crates.io/crates/lapack-sys) and so on.
+#[cfg(feature = "bfloat16")]
+pub mod ffi {
+    #![allow(non_camel_case_types, non_upper_case_globals)]
+
+    use core::ffi::{c_char, c_double, c_float, c_int};
+
+    /// Integer type of BLAS interface, `i64` for ILP64 build
+    #[cfg(not(openblas_ilp64))]
+    pub type blasint = i32;
+    /// Integer type of BLAS interface, `i64` for ILP64 build
+    #[cfg(openblas_ilp64)]
+    pub type blasint = i64;
+
+    /// Raw bits of bfloat16, i.e. upper 16 bits of `f32`
+    pub type bfloat16 = u16;
+
+    pub type CBLAS_ORDER = c_int;
+    pub const CblasRowMajor: CBLAS_ORDER = 101;
+    pub const CblasColMajor: CBLAS_ORDER = 102;
+
+    pub type CBLAS_TRANSPOSE = c_int;
+    pub const CblasNoTrans: CBLAS_TRANSPOSE = 111;
+    pub const CblasTrans: CBLAS_TRANSPOSE = 112;
+    pub const CblasConjTrans: CBLAS_TRANSPOSE = 113;
+
+    // bfloat16 routines in Fortran interface, built by `BUILD_BFLOAT16=1`
+    extern "C" {
+        pub fn sbstobf16_(
+            n: *const blasint,
+            input: *const c_float,
+            incin: *const blasint,
+            output: *mut bfloat16,
+            incout: *const blasint,
+        );
+        pub fn sbdtobf16_(
+            n: *const blasint,
+            input: *const c_double,
+            incin: *const blasint,
+            output: *mut bfloat16,
+            incout: *const blasint,
+        );
+        pub fn sbf16tos_(
+            n: *const blasint,
+            input: *const bfloat16,
+            incin: *const blasint,
+            output: *mut c_float,
+            incout: *const blasint,
+        );
+        pub fn dbf16tod_(
+            n: *const blasint,
+            input: *const bfloat16,
+            incin: *const blasint,
+            output: *mut c_double,
+            incout: *const blasint,
+        );
+        pub fn sbdot_(
+            n: *const blasint,
+            x: *const bfloat16,
+            incx: *const blasint,
+            y: *const bfloat16,
+            incy: *const blasint,
+        ) -> c_float;
+        pub fn sbgemv_(
+            trans: *const c_char,
+            m: *const blasint,
+            n: *const blasint,
+            alpha: *const c_float,
+            a: *const bfloat16,
+            lda: *const blasint,
+            x: *const bfloat16,
+            incx: *const blasint,
+            beta: *const c_float,
+            y: *mut c_float,
+            incy: *const blasint,
+        );
+        pub fn sbgemm_(
+            transa: *const c_char,
+            transb: *const c_char,
+            m: *const blasint,
+            n: *const blasint,
+            k: *const blasint,
+            alpha: *const c_float,
+            a: *const bfloat16,
+            lda: *const blasint,
+            b: *const bfloat16,
+            ldb: *const blasint,
+            beta: *const c_float,
+            c: *mut c_float,
+            ldc: *const blasint,
+        );
+    }
+
+    // bfloat16 routines in CBLAS interface
+    #[cfg(feature = "cblas")]
+    extern "C" {
+        pub fn cblas_sbstobf16(
+            n: blasint,
+            input: *const c_float,
+            incin: blasint,
+            output: *mut bfloat16,
+            incout: blasint,
+        );
+        pub fn cblas_sbdtobf16(
+            n: blasint,
+            input: *const c_double,
+            incin: blasint,
+            output: *mut bfloat16,
+            incout: blasint,
+        );
+        pub fn cblas_sbf16tos(
+            n: blasint,
+            input: *const bfloat16,
+            incin: blasint,
+            output: *mut c_float,
+            incout: blasint,
+        );
+        pub fn cblas_dbf16tod(
+            n: blasint,
+            input: *const bfloat16,
+            incin: blasint,
+            output: *mut c_double,
+            incout: blasint,
+        );
+        pub fn cblas_sbdot(
+            n: blasint,
+            x: *const bfloat16,
+            incx: blasint,
+            y: *const bfloat16,
+            incy: blasint,
+        ) -> c_float;
+        pub fn cblas_sbgemv(
+            order: CBLAS_ORDER,
+            trans: CBLAS_TRANSPOSE,
+            m: blasint,
+            n: blasint,
+            alpha: c_float,
+            a: *const bfloat16,
+            lda: blasint,
+            x: *const bfloat16,
+            incx: blasint,
+            beta: c_float,
+            y: *mut c_float,
+            incy: blasint,
+        );
+        pub fn cblas_sbgemm(
+            order: CBLAS_ORDER,
+            transa: CBLAS_TRANSPOSE,
+            transb: CBLAS_TRANSPOSE,
+            m: blasint,
+            n: blasint,
+            k: blasint,
+            alpha: c_float,
+            a: *const bfloat16,
+            lda: blasint,
+            b: *const bfloat16,
+            ldb: blasint,
+            beta: c_float,
+            c: *mut c_float,
+            ldc: blasint,
+        );
+    }
+}
@@ -0,0 +1,19 @@
+#![cfg(feature = "bfloat16")]
+
+use openblas_src::ffi::*;
+
+#[test]
+fn sbdot() {
+    let x: Vec<f32> = vec![1.0, 2.0, 3.0];
+    let y: Vec<f32> = vec![4.0, 5.0, 6.0];
+    let n = x.len() as blasint;
+    let inc = 1;
+    let mut x16 = vec![0; x.len()];
+    let mut y16 = vec![0; y.len()];
+    unsafe {
+        sbstobf16_(&n, x.as_ptr(), &inc, x16.as_mut_ptr(), &inc);
+        sbstobf16_(&n, y.as_ptr(), &inc, y16.as_mut_ptr(), &inc);
+        // These small integers are exactly representable in bfloat16
+        assert_eq!(sbdot_(&n, x16.as_ptr(), &inc, y16.as_ptr(), &inc), 32.0);
+    }
+}
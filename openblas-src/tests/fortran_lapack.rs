@@ -10,7 +10,8 @@
 //   GitHub Actions's macOS instance says gfotran is "installed",
 //   but it is too fragile me to give up using it.
 //
-#![cfg(target_os = "linux")]
+// The integer arguments below are 32-bit, i.e. this test assumes LP64 interface.
+#![cfg(all(target_os = "linux", not(feature = "ilp64")))]
 
 extern crate openblas_src as _src;
 
@@ -0,0 +1,85 @@
+// `static-fortran-runtime` feature links libgfortran statically,
+// i.e. test executable does not depend on `libgfortran.so`.
+//
+// The integer arguments below are 32-bit, i.e. this test assumes LP64 interface.
+#![cfg(all(
+    target_os = "linux",
+    feature = "static-fortran-runtime",
+    not(feature = "ilp64")
+))]
+
+extern crate openblas_src as _src;
+
+use std::process::Command;
+
+extern "C" {
+    fn dormbr_(
+        vect: *const u8,
+        side: *const u8,
+        trans: *const u8,
+        m: *const i32,
+        n: *const i32,
+        k: *const i32,
+        A: *const f64,
+        lda: *const i32,
+        tau: *const f64,
+        C: *mut f64,
+        ldc: *const i32,
+        work: *mut f64,
+        lwork: *const i32,
+        info: *mut i32,
+    );
+}
+
+// `dormbr_` concatenates strings using libgfortran, e.g. `_gfortran_concat_string`
+fn call_dormbr() {
+    let (m, n, k) = (1, 1, 1);
+    let a = [0.0];
+    let mut c = [0.0];
+    let mut work = [0.0];
+    let mut info = 0;
+    unsafe {
+        dormbr_(
+            &b'Q',
+            &b'L',
+            &b'N',
+            &m,
+            &n,
+            &k,
+            a.as_ptr(),
+            &1,
+            &0.0,
+            c.as_mut_ptr(),
+            &1,
+            work.as_mut_ptr(),
+            &1,
+            &mut info,
+        );
+    }
+}
+
+#[test]
+fn no_dynamic_fortran_runtime() {
+    call_dormbr();
+    let exe = std::env::current_exe().unwrap();
+    let output = Command::new("objdump")
+        .arg("-p")
+        .arg(&exe)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let needed: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("NEEDED"))
+        .map(|lib| lib.trim().to_string())
+        .collect();
+    assert!(!needed.is_empty());
+    for lib in &needed {
+        assert!(
+            !lib.starts_with("libgfortran") && !lib.starts_with("libquadmath"),
+            "{} depends on {}",
+            exe.display(),
+            lib
+        );
+    }
+}